@@ -0,0 +1,41 @@
+// Compares the speed of the execution engines.
+//
+// Run with: cargo bench
+
+extern crate bf_lib;
+
+use bf_lib::traits::*;
+use bf_lib::bf_lexer::*;
+use bf_lib::bf_vm::*;
+use bf_lib::bf_closure::ClosureProgram;
+use bf_lib::bf_bytecode::Bytecode;
+
+use std::time::Instant;
+
+// Nested counting loops with no output, so only the
+//  interpreter loop itself is being measured.
+const PROGRAM : &str = "++++++++++++++++[>++++++++++++++++[>++++++++++++++++[>++++++++++++++++[>++++++++++++++++[>++++++++++++++++[>+>+<<-]<-]<-]<-]<-]<-]";
+
+fn main() {
+    let tokens = match BFLexer::parse(String::from(PROGRAM)) {
+        LexResult::Success(tokens) => tokens,
+        LexResult::Error { message, .. } => panic!("{}", message)
+    };
+
+    let start = Instant::now();
+    let mut vm = BFVM::new(VMSettings::new());
+    vm.run(tokens.clone());
+    println!("interpreter:  {:?}", start.elapsed());
+
+    let start = Instant::now();
+    let mut vm = BFVM::new(VMSettings::new());
+    let program = ClosureProgram::compile(&tokens);
+    program.run(&mut vm);
+    println!("closures:     {:?}", start.elapsed());
+
+    let start = Instant::now();
+    let mut vm = BFVM::new(VMSettings::new());
+    let bytecode = Bytecode::compile(&tokens).unwrap();
+    vm.run_bytecode(&bytecode);
+    println!("bytecode:     {:?}", start.elapsed());
+}
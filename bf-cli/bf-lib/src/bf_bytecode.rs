@@ -0,0 +1,148 @@
+use bf_lexer::*;
+
+// Compact bytecode for the interpreter loop.
+//
+// Every instruction is a single u32: the low 8 bits hold
+//  the opcode and the high 24 bits hold a signed operand.
+//  This packs four times as many instructions into the
+//  cache as BFToken does, and jumps are relative so no
+//  separate position needs to be stored.
+//
+//  31                     8 7        0
+//  +-----------------------+----------+
+//  |        operand        |  opcode  |
+//  +-----------------------+----------+
+//
+// SetBlock splits its operand into a 16 bit count in the
+//  high bits and the 8 bit value below it.
+
+// Stop running - always the last instruction
+pub const HALT : u32 = 0;
+// Move the data pointer by operand
+pub const MOVE : u32 = 1;
+// Wrapping add operand to the current cell
+pub const ADD : u32 = 2;
+// Write the current cell
+pub const OUTPUT : u32 = 3;
+// Read into the current cell
+pub const INPUT : u32 = 4;
+// If the current cell is 0, jump by operand to the matching JUMP_NON_ZERO
+pub const JUMP_ZERO : u32 = 5;
+// If the current cell isn't 0, jump by operand to the matching JUMP_ZERO
+pub const JUMP_NON_ZERO : u32 = 6;
+// Set count cells to value and move to the last one
+pub const SET_BLOCK : u32 = 7;
+// Add the current cell to the cell at operand, then clear it
+pub const ADD_TO : u32 = 8;
+// Print the cells around the data pointer to stderr
+pub const DEBUG : u32 = 9;
+
+// Range of the signed 24 bit operand
+const OPERAND_MAX : isize = (1 << 23) - 1;
+const OPERAND_MIN : isize = -(1 << 23);
+
+// Largest count SET_BLOCK can hold
+const SET_BLOCK_MAX : usize = 0xFFFF;
+
+pub struct Bytecode {
+    pub code : Vec<u32>,
+
+    // Token each instruction came from, so errors can point
+    //  at the source. Kept out of code so the dispatch loop
+    //  stays compact.
+    pub tokens : Vec<usize>
+}
+
+// Packs an opcode with a signed operand.
+fn encode(opcode : u32, operand : isize) -> u32 {
+    ((operand as i32 as u32) << 8) | opcode
+}
+
+// Unpacks the signed operand of an instruction.
+#[inline(always)]
+pub fn operand(instruction : u32) -> isize {
+    ((instruction as i32) >> 8) as isize
+}
+
+impl Bytecode {
+
+    // Lowers lexer tokens into bytecode.
+    //  Fails if an operand doesn't fit in 24 bits.
+    pub fn compile(data : &[BFToken]) -> Result<Bytecode, String> {
+        use bf_lexer::BFTokenType::*;
+
+        let mut code : Vec<u32> = Vec::with_capacity(data.len() + 1);
+        let mut tokens : Vec<usize> = Vec::with_capacity(data.len() + 1);
+        let mut loop_stack : Vec<usize> = vec![];
+
+        for (index, token) in data.iter().enumerate() {
+            match token.token_type {
+                IncrementPtr(x) => push_move(&mut code, x as isize),
+                DecrementPtr(x) => push_move(&mut code, -(x as isize)),
+                IncrementData(x) => code.push(encode(ADD, (x as u8) as isize)),
+                DecrementData(x) => code.push(encode(ADD, (x as u8).wrapping_neg() as isize)),
+                Output => code.push(OUTPUT),
+                Input => code.push(INPUT),
+                LoopStart(_) => {
+                    loop_stack.push(code.len());
+                    code.push(JUMP_ZERO);
+                },
+                LoopEnd(_) => {
+                    let start = loop_stack.pop().ok_or(format!("Unmatched loop end at token {}", token.pos))?;
+                    let offset = (code.len() - start) as isize;
+                    if offset > OPERAND_MAX {
+                        return Err(String::from("Loop is too long for bytecode"));
+                    }
+                    code[start] = encode(JUMP_ZERO, offset);
+                    code.push(encode(JUMP_NON_ZERO, -offset));
+                },
+                SetBlock(x, y) => {
+                    // Blocks too big for one instruction are split
+                    //  into several, moving on one between them.
+                    let mut count = x;
+                    while count > SET_BLOCK_MAX {
+                        code.push(encode_set_block(SET_BLOCK_MAX, y));
+                        push_move(&mut code, 1);
+                        count -= SET_BLOCK_MAX;
+                    }
+                    code.push(encode_set_block(count, y));
+                },
+                AddCurrentUp(x) | AddCurrentDown(x) => {
+                    if x as isize > OPERAND_MAX {
+                        return Err(format!("Offset {} is too big for bytecode", x));
+                    }
+                    let offset = if let AddCurrentUp(_) = token.token_type { x as isize } else { -(x as isize) };
+                    code.push(encode(ADD_TO, offset));
+                },
+                Debug => code.push(encode(DEBUG, 0))
+            }
+            tokens.resize(code.len(), index);
+        }
+
+        if !loop_stack.is_empty() {
+            return Err(String::from("Unmatched loop start"));
+        }
+
+        code.push(HALT);
+        tokens.push(data.len());
+
+        Ok(Bytecode { code, tokens })
+    }
+}
+
+// Moves bigger than the operand are split up.
+fn push_move(code : &mut Vec<u32>, mut offset : isize) {
+    while offset > OPERAND_MAX {
+        code.push(encode(MOVE, OPERAND_MAX));
+        offset -= OPERAND_MAX;
+    }
+    while offset < OPERAND_MIN {
+        code.push(encode(MOVE, OPERAND_MIN));
+        offset -= OPERAND_MIN;
+    }
+    code.push(encode(MOVE, offset));
+}
+
+fn encode_set_block(count : usize, value : i8) -> u32 {
+    ((count as u32) << 16) | (((value as u8) as u32) << 8) | SET_BLOCK
+}
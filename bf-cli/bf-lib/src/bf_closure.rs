@@ -0,0 +1,138 @@
+use traits::*;
+use bf_lexer::*;
+use bf_vm::BFVM;
+
+// A single compiled instruction. Loops own the
+//  instructions in their body, so a program is a tree
+//  of closures rather than a flat list with jumps.
+type Op = Box<dyn Fn(&mut BFVM)>;
+
+// Alternative execution engine for BFVM.
+//
+// The token vector is compiled once into nested closures,
+//  so running the program doesn't need to re-dispatch on
+//  the token type or check the instruction pointer for
+//  every instruction - each closure already knows what it
+//  does and loops just call their body until the current
+//  cell is 0.
+pub struct ClosureProgram {
+    ops : Vec<Op>
+}
+
+impl ClosureProgram {
+    pub fn compile(data : &[BFToken]) -> ClosureProgram {
+        ClosureProgram {
+            ops : compile_block(data, 0, data.len())
+        }
+    }
+
+    // Runs the program against the VM's memory, starting
+    //  from its current data pointer.
+    pub fn run(&self, vm : &mut BFVM) -> VMResult {
+        for op in &self.ops {
+            op(vm);
+            if vm.halted() {
+                // Any error is picked up by finish
+                return vm.finish(VMResult::Interrupted);
+            }
+        }
+
+        vm.finish(VMResult::Success)
+    }
+}
+
+// Compiles the tokens in [start, end) into a list of closures.
+fn compile_block(data : &[BFToken], start : usize, end : usize) -> Vec<Op> {
+    use bf_lexer::BFTokenType::*;
+
+    let mut ops : Vec<Op> = vec![];
+    let mut i = start;
+
+    while i < end {
+        // Closures that move the data pointer or reach other
+        //  cells check the tape first, as BFVM::step does.
+        let token_type = data[i].token_type;
+        let inst_ptr = i;
+
+        let op : Op = match token_type {
+            IncrementPtr(x) => Box::new(move |vm| {
+                if vm.in_bounds(token_type, inst_ptr) {
+                    vm.data_ptr += x;
+                }
+            }),
+            DecrementPtr(x) => Box::new(move |vm| {
+                if vm.in_bounds(token_type, inst_ptr) {
+                    vm.data_ptr -= x;
+                }
+            }),
+            IncrementData(x) => Box::new(move |vm| {
+                vm.mem[vm.data_ptr] = vm.mem[vm.data_ptr].wrapping_add(x as i8);
+            }),
+            DecrementData(x) => Box::new(move |vm| {
+                vm.mem[vm.data_ptr] = vm.mem[vm.data_ptr].wrapping_sub(x as i8);
+            }),
+            Output => Box::new(move |vm| {
+                vm.output();
+                vm.error_at(inst_ptr);
+            }),
+            Input => Box::new(move |vm| {
+                vm.input();
+                vm.error_at(inst_ptr);
+            }),
+
+            // Compile the body and skip past the matching ]
+            LoopStart(x) => {
+                let body = compile_block(data, i + 1, x);
+                i = x;
+                Box::new(move |vm| {
+                    // Loops stop early on an error or interrupt,
+                    //  which is then picked up by run.
+                    while vm.mem[vm.data_ptr] != 0 && !vm.halted() {
+                        for op in &body {
+                            op(vm);
+                            if vm.halted() {
+                                return;
+                            }
+                        }
+                    }
+                })
+            },
+
+            // Loop ends are consumed by their LoopStart
+            LoopEnd(_) => unreachable!("Unmatched loop end"),
+
+            SetBlock(x, y) => Box::new(move |vm| {
+                if !vm.in_bounds(token_type, inst_ptr) {
+                    return;
+                }
+                for cell in &mut vm.mem[vm.data_ptr..vm.data_ptr + x] {
+                    *cell = y;
+                }
+                vm.data_ptr += x - 1;
+            }),
+            AddCurrentUp(x) => Box::new(move |vm| {
+                if !vm.in_bounds(token_type, inst_ptr) {
+                    return;
+                }
+                vm.mem[vm.data_ptr + x] = vm.mem[vm.data_ptr + x].wrapping_add(vm.mem[vm.data_ptr]);
+                vm.mem[vm.data_ptr] = 0;
+            }),
+            AddCurrentDown(x) => Box::new(move |vm| {
+                if !vm.in_bounds(token_type, inst_ptr) {
+                    return;
+                }
+                vm.mem[vm.data_ptr - x] = vm.mem[vm.data_ptr - x].wrapping_add(vm.mem[vm.data_ptr]);
+                vm.mem[vm.data_ptr] = 0;
+            }),
+            Debug => Box::new(move |vm| {
+                vm.debug(None);
+                vm.error_at(inst_ptr);
+            })
+        };
+
+        ops.push(op);
+        i += 1;
+    }
+
+    ops
+}
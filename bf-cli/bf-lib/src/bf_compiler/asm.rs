@@ -0,0 +1,225 @@
+use traits::*;
+use bf_lexer::*;
+use bf_compiler::*;
+
+// Assembler the generated source is written for.
+#[derive(PartialEq, Clone, Debug, Copy)]
+pub enum AsmSyntax {
+    // GNU as, using its Intel syntax mode
+    Gas,
+    Nasm,
+}
+
+// Translates BF tokens into x86-64 assembly for Linux.
+//
+// The program talks to the kernel directly with the
+//  read, write and exit syscalls, so it can be built
+//  without a C library:
+//
+//      as -o prog.o prog.s && ld -o prog prog.o
+//
+// Register usage:
+//  rbx     Address of the current cell
+//  rax     Scratch, syscall number
+//  rdi     Syscall argument 1
+//  rsi     Syscall argument 2
+//  rdx     Syscall argument 3
+//  rcx     Block set counter
+pub struct AsmCompiler {
+    settings : CompileSettings,
+
+    pub syntax : AsmSyntax
+}
+
+impl AsmCompiler {
+    pub fn new(settings : CompileSettings) -> AsmCompiler {
+        AsmCompiler {
+            settings,
+            syntax : AsmSyntax::Gas
+        }
+    }
+
+    // Size specifier for a memory operand holding one cell
+    fn size(&self) -> &'static str {
+        match (self.syntax, self.settings.cell_width) {
+            (AsmSyntax::Gas, CellWidth::Bits8) => "byte ptr",
+            (AsmSyntax::Gas, CellWidth::Bits16) => "word ptr",
+            (AsmSyntax::Gas, CellWidth::Bits32) => "dword ptr",
+            (AsmSyntax::Nasm, CellWidth::Bits8) => "byte",
+            (AsmSyntax::Nasm, CellWidth::Bits16) => "word",
+            (AsmSyntax::Nasm, CellWidth::Bits32) => "dword",
+        }
+    }
+
+    // The part of rax that holds one cell
+    fn accumulator(&self) -> &'static str {
+        match self.settings.cell_width {
+            CellWidth::Bits8 => "al",
+            CellWidth::Bits16 => "ax",
+            CellWidth::Bits32 => "eax",
+        }
+    }
+
+    // Memory operand for the cell at the data pointer plus an offset
+    fn cell(&self, offset : isize) -> String {
+        let bytes = offset * self.settings.cell_width.bytes() as isize;
+        if bytes > 0 {
+            format!("{} [rbx + {}]", self.size(), bytes)
+        } else if bytes < 0 {
+            format!("{} [rbx - {}]", self.size(), -bytes)
+        } else {
+            format!("{} [rbx]", self.size())
+        }
+    }
+
+    fn header(&self, out : &mut String) {
+        let tape_bytes = self.settings.tape_size * self.settings.cell_width.bytes();
+        let start = self.settings.start_ptr() * self.settings.cell_width.bytes();
+
+        match self.syntax {
+            AsmSyntax::Gas => {
+                out.push_str("# Generated by bf-cli\n");
+                out.push_str("    .intel_syntax noprefix\n");
+                out.push_str("    .globl _start\n\n");
+                out.push_str("    .bss\n");
+                out.push_str(&format!("    .lcomm tape, {}\n", tape_bytes));
+                out.push_str("    .lcomm inbuf, 1\n\n");
+                out.push_str("    .text\n");
+                out.push_str("_start:\n");
+                out.push_str(&format!("    lea rbx, [rip + tape + {}]\n", start));
+            },
+            AsmSyntax::Nasm => {
+                out.push_str("; Generated by bf-cli\n");
+                out.push_str("    bits 64\n");
+                out.push_str("    global _start\n\n");
+                out.push_str("    section .bss\n");
+                out.push_str(&format!("tape: resb {}\n", tape_bytes));
+                out.push_str("inbuf: resb 1\n\n");
+                out.push_str("    section .text\n");
+                out.push_str("_start:\n");
+                out.push_str(&format!("    lea rbx, [rel tape + {}]\n", start));
+            }
+        }
+    }
+}
+
+impl Compiler<BFToken> for AsmCompiler {
+
+    fn compile(&self, data : Vec<BFToken>) -> CompileResult {
+        use bf_lexer::BFTokenType::*;
+
+        if let Err(message) = self.settings.validate() {
+            return CompileResult::Error { message };
+        }
+
+        let width = self.settings.cell_width;
+        let bytes = width.bytes();
+        let current = self.cell(0);
+        let (inbuf_addr, inbuf) = match self.syntax {
+            AsmSyntax::Gas => ("[rip + inbuf]", "byte ptr [rip + inbuf]"),
+            AsmSyntax::Nasm => ("[rel inbuf]", "byte [rel inbuf]"),
+        };
+        let stos = match width {
+            CellWidth::Bits8 => "stosb",
+            CellWidth::Bits16 => "stosw",
+            CellWidth::Bits32 => "stosd",
+        };
+
+        let mut out = String::new();
+        self.header(&mut out);
+
+        // Each loop and input gets a unique label number
+        let mut label = 0;
+        let mut loop_stack : Vec<usize> = vec![];
+
+        for token in data {
+            match token.token_type {
+                IncrementPtr(x) => out.push_str(&format!("    add rbx, {}\n", x * bytes)),
+                DecrementPtr(x) => out.push_str(&format!("    sub rbx, {}\n", x * bytes)),
+                IncrementData(x) => out.push_str(&format!("    add {}, {}\n", current, width.wrap(x as i64))),
+                DecrementData(x) => out.push_str(&format!("    sub {}, {}\n", current, width.wrap(x as i64))),
+                Output => {
+                    // write(stdout, rbx, 1) - cells are little endian so
+                    //  the low byte is the first one.
+                    out.push_str("    mov eax, 1\n");
+                    out.push_str("    mov edi, 1\n");
+                    out.push_str("    mov rsi, rbx\n");
+                    out.push_str("    mov edx, 1\n");
+                    out.push_str("    syscall\n");
+                },
+                Input => {
+                    // read(stdin, inbuf, 1) - then widen the byte into
+                    //  the cell, or apply the EOF behaviour if nothing
+                    //  was read.
+                    label += 1;
+                    out.push_str("    xor eax, eax\n");
+                    out.push_str("    xor edi, edi\n");
+                    out.push_str(&format!("    lea rsi, {}\n", inbuf_addr));
+                    out.push_str("    mov edx, 1\n");
+                    out.push_str("    syscall\n");
+                    out.push_str("    cmp rax, 1\n");
+                    out.push_str(&format!("    jne input_{}_eof\n", label));
+                    out.push_str(&format!("    movzx eax, {}\n", inbuf));
+                    out.push_str(&format!("    mov {}, {}\n", current, self.accumulator()));
+                    out.push_str(&format!("    jmp input_{}_done\n", label));
+                    out.push_str(&format!("input_{}_eof:\n", label));
+                    match self.settings.eof {
+                        EofBehaviour::Zero => out.push_str(&format!("    mov {}, 0\n", current)),
+                        EofBehaviour::MinusOne => out.push_str(&format!("    mov {}, -1\n", current)),
+                        EofBehaviour::Unchanged => { }
+                    }
+                    out.push_str(&format!("input_{}_done:\n", label));
+                },
+                LoopStart(_) => {
+                    label += 1;
+                    loop_stack.push(label);
+                    out.push_str(&format!("    cmp {}, 0\n", current));
+                    out.push_str(&format!("    je loop_{}_end\n", label));
+                    out.push_str(&format!("loop_{}_start:\n", label));
+                },
+                LoopEnd(_) => {
+                    let start = match loop_stack.pop() {
+                        Some(start) => start,
+                        None => return CompileResult::Error { message : format!("Unmatched loop end at token {}", token.pos) }
+                    };
+                    out.push_str(&format!("    cmp {}, 0\n", current));
+                    out.push_str(&format!("    jne loop_{}_start\n", start));
+                    out.push_str(&format!("loop_{}_end:\n", start));
+                },
+                SetBlock(x, y) => {
+                    out.push_str("    mov rdi, rbx\n");
+                    out.push_str(&format!("    mov ecx, {}\n", x));
+                    out.push_str(&format!("    mov eax, {}\n", width.wrap(y as i64)));
+                    out.push_str(&format!("    rep {}\n", stos));
+                    if x > 1 {
+                        out.push_str(&format!("    add rbx, {}\n", (x - 1) * bytes));
+                    }
+                },
+                AddCurrentUp(x) => {
+                    out.push_str(&format!("    mov {}, {}\n", self.accumulator(), current));
+                    out.push_str(&format!("    add {}, {}\n", self.cell(x as isize), self.accumulator()));
+                    out.push_str(&format!("    mov {}, 0\n", current));
+                },
+                AddCurrentDown(x) => {
+                    out.push_str(&format!("    mov {}, {}\n", self.accumulator(), current));
+                    out.push_str(&format!("    add {}, {}\n", self.cell(-(x as isize)), self.accumulator()));
+                    out.push_str(&format!("    mov {}, 0\n", current));
+                },
+
+                // Removed by bf_compiler::compile
+                Debug => unreachable!()
+            }
+        }
+
+        if !loop_stack.is_empty() {
+            return CompileResult::Error { message : String::from("Unmatched loop start") };
+        }
+
+        // exit(0)
+        out.push_str("    mov eax, 60\n");
+        out.push_str("    xor edi, edi\n");
+        out.push_str("    syscall\n");
+
+        CompileResult::Success(out.into_bytes())
+    }
+}
@@ -0,0 +1,100 @@
+use traits::*;
+use bf_lexer::*;
+use bf_compiler::*;
+
+// Translates BF tokens into a standalone C program
+//  that can be built with the system C compiler.
+pub struct CCompiler {
+    settings : CompileSettings
+}
+
+impl CCompiler {
+    pub fn new(settings : CompileSettings) -> CCompiler {
+        CCompiler {
+            settings
+        }
+    }
+
+    // Unsigned types are used so that + and - wrap
+    //  without relying on undefined behaviour.
+    fn cell_type(&self) -> &'static str {
+        match self.settings.cell_width {
+            CellWidth::Bits8 => "uint8_t",
+            CellWidth::Bits16 => "uint16_t",
+            CellWidth::Bits32 => "uint32_t",
+        }
+    }
+}
+
+impl Compiler<BFToken> for CCompiler {
+
+    fn compile(&self, data : Vec<BFToken>) -> CompileResult {
+        use bf_lexer::BFTokenType::*;
+
+        if let Err(message) = self.settings.validate() {
+            return CompileResult::Error { message };
+        }
+
+        let cell = self.cell_type();
+        let mut out = String::new();
+
+        out.push_str("#include <stdio.h>\n");
+        out.push_str("#include <stdint.h>\n\n");
+        out.push_str(&format!("static {} tape[{}];\n\n", cell, self.settings.tape_size));
+        out.push_str("int main(void) {\n");
+        out.push_str(&format!("    {} *p = tape + {};\n", cell, self.settings.start_ptr()));
+
+        let mut indent = String::from("    ");
+        for token in data {
+            match token.token_type {
+                IncrementPtr(x) => out.push_str(&format!("{}p += {};\n", indent, x)),
+                DecrementPtr(x) => out.push_str(&format!("{}p -= {};\n", indent, x)),
+                IncrementData(x) => out.push_str(&format!("{}*p += {};\n", indent, x)),
+                DecrementData(x) => out.push_str(&format!("{}*p -= {};\n", indent, x)),
+                Output => out.push_str(&format!("{}putchar(*p);\n", indent)),
+                Input => {
+                    // Flush first so prompts are visible before blocking on input
+                    out.push_str(&format!("{}fflush(stdout);\n", indent));
+                    let store = match self.settings.eof {
+                        EofBehaviour::Zero => format!("*p = c == EOF ? 0 : ({})c;", cell),
+                        EofBehaviour::MinusOne => format!("*p = ({})c;", cell),
+                        EofBehaviour::Unchanged => format!("if (c != EOF) *p = ({})c;", cell),
+                    };
+                    out.push_str(&format!("{}{{ int c = getchar(); {} }}\n", indent, store));
+                },
+                LoopStart(_) => {
+                    out.push_str(&format!("{}while (*p) {{\n", indent));
+                    indent.push_str("    ");
+                },
+                LoopEnd(_) => {
+                    if indent.len() <= 4 {
+                        return CompileResult::Error { message : format!("Unmatched loop end at token {}", token.pos) };
+                    }
+                    let len = indent.len() - 4;
+                    indent.truncate(len);
+                    out.push_str(&format!("{}}}\n", indent));
+                },
+                SetBlock(x, y) => {
+                    out.push_str(&format!("{}for (size_t i = 0; i < {}; i++) p[i] = ({}){};\n", indent, x, cell, y));
+                    if x > 1 {
+                        out.push_str(&format!("{}p += {};\n", indent, x - 1));
+                    }
+                },
+                AddCurrentUp(x) => out.push_str(&format!("{}p[{}] += *p;\n{}*p = 0;\n", indent, x, indent)),
+                AddCurrentDown(x) => out.push_str(&format!("{}p[-{}] += *p;\n{}*p = 0;\n", indent, x, indent)),
+
+                // Removed by bf_compiler::compile
+                Debug => unreachable!()
+            }
+        }
+
+        if indent.len() != 4 {
+            return CompileResult::Error { message : String::from("Unmatched loop start") };
+        }
+
+        out.push_str("    return 0;\n");
+        out.push_str("}\n");
+
+        CompileResult::Success(out.into_bytes())
+    }
+}
@@ -0,0 +1,299 @@
+use traits::*;
+use bf_lexer::*;
+use bf_compiler::*;
+
+// Address the program is loaded at
+const BASE_ADDRESS : u64 = 0x400000;
+
+// Sizes of the ELF file header and the single program header
+const ELF_HEADER_SIZE : usize = 64;
+const PROGRAM_HEADER_SIZE : usize = 56;
+
+// Translates BF tokens straight into a static Linux x86-64
+//  ELF executable, without an assembler or linker.
+//
+// The file holds a single loadable segment: the headers,
+//  then the machine code, then the tape, which takes up
+//  no space in the file as the kernel zero fills it.
+//
+// The generated code follows the same layout as
+//  AsmCompiler, with rbx holding the address of the
+//  current cell.
+pub struct ElfCompiler {
+    settings : CompileSettings
+}
+
+impl ElfCompiler {
+    pub fn new(settings : CompileSettings) -> ElfCompiler {
+        ElfCompiler {
+            settings
+        }
+    }
+}
+
+// Encodes the handful of x86-64 instructions the
+//  compiler needs.
+struct Encoder {
+    code : Vec<u8>,
+    width : CellWidth
+}
+
+impl Encoder {
+    fn emit(&mut self, bytes : &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn emit_u32(&mut self, value : u32) {
+        self.code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Operand size prefix needed for 16 bit cells
+    fn prefix(&mut self) {
+        if self.width == CellWidth::Bits16 {
+            self.code.push(0x66);
+        }
+    }
+
+    // Writes the opcode for the cell sized form of an
+    //  instruction, picking the byte form for 8 bit cells.
+    fn opcode(&mut self, byte_form : u8, wide_form : u8) {
+        self.prefix();
+        self.code.push(if self.width == CellWidth::Bits8 { byte_form } else { wide_form });
+    }
+
+    // ModRM (and displacement) for [rbx + offset] with the
+    //  given reg field.
+    fn cell(&mut self, reg : u8, offset : isize) {
+        let disp = offset * self.width.bytes() as isize;
+        if disp == 0 {
+            self.code.push((reg << 3) | 3);
+        } else if (-128..=127).contains(&disp) {
+            self.code.push(0x40 | (reg << 3) | 3);
+            self.code.push(disp as i8 as u8);
+        } else {
+            self.code.push(0x80 | (reg << 3) | 3);
+            self.emit_u32(disp as i32 as u32);
+        }
+    }
+
+    // Cell sized immediate
+    fn immediate(&mut self, value : u64) {
+        match self.width {
+            CellWidth::Bits8 => self.code.push(value as u8),
+            CellWidth::Bits16 => self.code.extend_from_slice(&(value as u16).to_le_bytes()),
+            CellWidth::Bits32 => self.emit_u32(value as u32),
+        }
+    }
+
+    // add/sub rbx, imm32
+    fn move_ptr(&mut self, bytes : usize, up : bool) {
+        self.emit(&[0x48, 0x81, if up { 0xC3 } else { 0xEB }]);
+        self.emit_u32(bytes as u32);
+    }
+
+    // add/sub cell, imm
+    fn add_cell(&mut self, value : u64, up : bool) {
+        self.opcode(0x80, 0x81);
+        self.cell(if up { 0 } else { 5 }, 0);
+        self.immediate(value);
+    }
+
+    // mov cell, imm
+    fn set_cell(&mut self, offset : isize, value : u64) {
+        self.opcode(0xC6, 0xC7);
+        self.cell(0, offset);
+        self.immediate(value);
+    }
+
+    // cmp cell, 0
+    fn test_cell(&mut self) {
+        self.opcode(0x80, 0x83);
+        self.cell(7, 0);
+        self.code.push(0);
+    }
+
+    // Conditional jump with a 32 bit offset. Returns the
+    //  position of the offset so it can be patched.
+    fn jump(&mut self, condition : u8) -> usize {
+        self.emit(&[0x0F, condition]);
+        self.emit_u32(0);
+        self.code.len() - 4
+    }
+
+    // Points the jump offset at 'pos' to the given target.
+    fn patch(&mut self, pos : usize, target : usize) {
+        let offset = (target as isize - (pos as isize + 4)) as i32;
+        self.code[pos..pos + 4].copy_from_slice(&offset.to_le_bytes());
+    }
+
+    // cell[offset] += cell, current cell = 0
+    fn add_to(&mut self, offset : isize) {
+        // mov al/ax/eax, cell
+        self.opcode(0x8A, 0x8B);
+        self.cell(0, 0);
+        // add cell[offset], al/ax/eax
+        self.opcode(0x00, 0x01);
+        self.cell(0, offset);
+        self.set_cell(0, 0);
+    }
+
+    fn syscall(&mut self) {
+        self.emit(&[0x0F, 0x05]);
+    }
+}
+
+impl Compiler<BFToken> for ElfCompiler {
+
+    fn compile(&self, data : Vec<BFToken>) -> CompileResult {
+        use bf_lexer::BFTokenType::*;
+
+        if let Err(message) = self.settings.validate() {
+            return CompileResult::Error { message };
+        }
+
+        let width = self.settings.cell_width;
+        let bytes = width.bytes();
+        let mut enc = Encoder { code : vec![], width };
+
+        // mov rbx, imm64 - the tape address is patched in
+        //  once the size of the code is known.
+        enc.emit(&[0x48, 0xBB]);
+        let tape_address_pos = enc.code.len();
+        enc.emit(&[0; 8]);
+
+        let mut loop_stack : Vec<usize> = vec![];
+
+        for token in data {
+            match token.token_type {
+                IncrementPtr(x) => enc.move_ptr(x * bytes, true),
+                DecrementPtr(x) => enc.move_ptr(x * bytes, false),
+                IncrementData(x) => enc.add_cell(width.wrap(x as i64), true),
+                DecrementData(x) => enc.add_cell(width.wrap(x as i64), false),
+                Output => {
+                    // write(1, rbx, 1)
+                    enc.emit(&[0xB8, 1, 0, 0, 0]);          // mov eax, 1
+                    enc.emit(&[0xBF, 1, 0, 0, 0]);          // mov edi, 1
+                    enc.emit(&[0x48, 0x89, 0xDE]);          // mov rsi, rbx
+                    enc.emit(&[0xBA, 1, 0, 0, 0]);          // mov edx, 1
+                    enc.syscall();
+                },
+                Input => {
+                    // read(0, rbx, 1) - this only fills the low byte
+                    //  of the cell, so wider cells are zero extended.
+                    enc.emit(&[0x31, 0xC0]);                // xor eax, eax
+                    enc.emit(&[0x31, 0xFF]);                // xor edi, edi
+                    enc.emit(&[0x48, 0x89, 0xDE]);          // mov rsi, rbx
+                    enc.emit(&[0xBA, 1, 0, 0, 0]);          // mov edx, 1
+                    enc.syscall();
+                    enc.emit(&[0x48, 0x83, 0xF8, 0x01]);    // cmp rax, 1
+                    let eof = enc.jump(0x85);               // jne eof
+                    if width != CellWidth::Bits8 {
+                        enc.emit(&[0x0F, 0xB6, 0x03]);      // movzx eax, byte [rbx]
+                        enc.opcode(0x88, 0x89);             // mov cell, ax/eax
+                        enc.cell(0, 0);
+                    }
+                    enc.emit(&[0xE9, 0, 0, 0, 0]);          // jmp done
+                    let done = enc.code.len() - 4;
+                    let eof_target = enc.code.len();
+                    enc.patch(eof, eof_target);
+                    match self.settings.eof {
+                        EofBehaviour::Zero => enc.set_cell(0, 0),
+                        EofBehaviour::MinusOne => enc.set_cell(0, width.wrap(-1)),
+                        EofBehaviour::Unchanged => { }
+                    }
+                    let done_target = enc.code.len();
+                    enc.patch(done, done_target);
+                },
+                LoopStart(_) => {
+                    enc.test_cell();
+                    let end = enc.jump(0x84);               // je end
+                    loop_stack.push(end);
+                },
+                LoopEnd(_) => {
+                    let end = match loop_stack.pop() {
+                        Some(end) => end,
+                        None => return CompileResult::Error { message : format!("Unmatched loop end at token {}", token.pos) }
+                    };
+                    enc.test_cell();
+                    let start = enc.jump(0x85);             // jne start
+                    enc.patch(start, end + 4);
+                    let end_target = enc.code.len();
+                    enc.patch(end, end_target);
+                },
+                SetBlock(x, y) => {
+                    enc.emit(&[0x48, 0x89, 0xDF]);          // mov rdi, rbx
+                    enc.emit(&[0xB9]);                      // mov ecx, x
+                    enc.emit_u32(x as u32);
+                    enc.emit(&[0xB8]);                      // mov eax, y
+                    enc.emit_u32(width.wrap(y as i64) as u32);
+                    enc.emit(&[0xF3]);                      // rep stos
+                    enc.opcode(0xAA, 0xAB);
+                    if x > 1 {
+                        enc.move_ptr((x - 1) * bytes, true);
+                    }
+                },
+                AddCurrentUp(x) => enc.add_to(x as isize),
+                AddCurrentDown(x) => enc.add_to(-(x as isize)),
+
+                // Removed by bf_compiler::compile
+                Debug => unreachable!()
+            }
+        }
+
+        if !loop_stack.is_empty() {
+            return CompileResult::Error { message : String::from("Unmatched loop start") };
+        }
+
+        // exit(0)
+        enc.emit(&[0xB8, 60, 0, 0, 0]);                     // mov eax, 60
+        enc.emit(&[0x31, 0xFF]);                            // xor edi, edi
+        enc.syscall();
+
+        // Everything in the file, the tape follows on after this
+        let code_offset = ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE;
+        let file_size = code_offset + enc.code.len();
+        let tape_offset = (file_size + 15) & !15;
+        let mem_size = tape_offset + self.settings.tape_size * bytes;
+
+        let tape_address = BASE_ADDRESS + (tape_offset + self.settings.start_ptr() * bytes) as u64;
+        enc.code[tape_address_pos..tape_address_pos + 8].copy_from_slice(&tape_address.to_le_bytes());
+
+        let mut out : Vec<u8> = Vec::with_capacity(file_size);
+
+        // ELF header
+        out.extend_from_slice(&[0x7F, b'E', b'L', b'F']);
+        out.push(2);                                        // 64 bit
+        out.push(1);                                        // Little endian
+        out.push(1);                                        // ELF version
+        out.push(0);                                        // System V ABI
+        out.extend_from_slice(&[0; 8]);                     // Padding
+        out.extend_from_slice(&2u16.to_le_bytes());         // Executable
+        out.extend_from_slice(&0x3Eu16.to_le_bytes());      // x86-64
+        out.extend_from_slice(&1u32.to_le_bytes());         // Version
+        out.extend_from_slice(&(BASE_ADDRESS + code_offset as u64).to_le_bytes()); // Entry point
+        out.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes());            // Program headers
+        out.extend_from_slice(&0u64.to_le_bytes());         // No section headers
+        out.extend_from_slice(&0u32.to_le_bytes());         // Flags
+        out.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+        out.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());         // One program header
+        out.extend_from_slice(&0u16.to_le_bytes());         // Section header size
+        out.extend_from_slice(&0u16.to_le_bytes());         // Section header count
+        out.extend_from_slice(&0u16.to_le_bytes());         // Section name index
+
+        // Program header - one read, write, execute segment
+        //  covering the whole file and the tape.
+        out.extend_from_slice(&1u32.to_le_bytes());         // Loadable
+        out.extend_from_slice(&7u32.to_le_bytes());         // RWX
+        out.extend_from_slice(&0u64.to_le_bytes());         // File offset
+        out.extend_from_slice(&BASE_ADDRESS.to_le_bytes()); // Virtual address
+        out.extend_from_slice(&BASE_ADDRESS.to_le_bytes()); // Physical address
+        out.extend_from_slice(&(file_size as u64).to_le_bytes());
+        out.extend_from_slice(&(mem_size as u64).to_le_bytes());
+        out.extend_from_slice(&0x1000u64.to_le_bytes());    // Alignment
+
+        out.extend_from_slice(&enc.code);
+
+        CompileResult::Success(out)
+    }
+}
@@ -0,0 +1,239 @@
+use traits::*;
+use bf_lexer::*;
+use bf_compiler::*;
+
+// Translates BF tokens into textual LLVM IR, which can then
+//  be optimised and built with llc or clang:
+//
+//      clang -O3 -o prog prog.ll
+//
+// The data pointer is kept as a tape index in a stack slot,
+//  which LLVM's mem2reg pass turns into a register. Input and
+//  output go through the C library's getchar and putchar.
+//
+// Opaque pointers are used, so LLVM 15 or newer is needed
+//  (or -opaque-pointers on LLVM 14).
+pub struct LlvmCompiler {
+    settings : CompileSettings
+}
+
+impl LlvmCompiler {
+    pub fn new(settings : CompileSettings) -> LlvmCompiler {
+        LlvmCompiler {
+            settings
+        }
+    }
+}
+
+// Keeps track of the numbered values and labels while
+//  writing out a function.
+struct Function {
+    body : String,
+    cell : &'static str,
+    next_value : usize,
+    next_label : usize
+}
+
+impl Function {
+    fn value(&mut self) -> String {
+        self.next_value += 1;
+        format!("%t{}", self.next_value)
+    }
+
+    fn line(&mut self, line : &str) {
+        self.body.push_str("  ");
+        self.body.push_str(line);
+        self.body.push('\n');
+    }
+
+    fn label(&mut self, label : &str) {
+        self.body.push_str(label);
+        self.body.push_str(":\n");
+    }
+
+    // Returns the address of the cell at the data pointer plus an offset
+    fn cell_address(&mut self, offset : isize) -> String {
+        let index = self.value();
+        self.line(&format!("{} = load i64, ptr %ptr", index));
+        let index = if offset != 0 {
+            let moved = self.value();
+            self.line(&format!("{} = add i64 {}, {}", moved, index, offset));
+            moved
+        } else {
+            index
+        };
+        let address = self.value();
+        let cell = self.cell;
+        self.line(&format!("{} = getelementptr inbounds {}, ptr @tape, i64 {}", address, cell, index));
+        address
+    }
+
+    // Loads a cell, returning its value
+    fn load(&mut self, address : &str) -> String {
+        let value = self.value();
+        let cell = self.cell;
+        self.line(&format!("{} = load {}, ptr {}", value, cell, address));
+        value
+    }
+
+    fn store(&mut self, address : &str, value : &str) {
+        let cell = self.cell;
+        self.line(&format!("store {} {}, ptr {}", cell, value, address));
+    }
+
+    fn move_ptr(&mut self, offset : isize) {
+        let index = self.value();
+        self.line(&format!("{} = load i64, ptr %ptr", index));
+        let moved = self.value();
+        self.line(&format!("{} = add i64 {}, {}", moved, index, offset));
+        self.line(&format!("store i64 {}, ptr %ptr", moved));
+    }
+}
+
+impl Compiler<BFToken> for LlvmCompiler {
+
+    fn compile(&self, data : Vec<BFToken>) -> CompileResult {
+        use bf_lexer::BFTokenType::*;
+
+        if let Err(message) = self.settings.validate() {
+            return CompileResult::Error { message };
+        }
+
+        let width = self.settings.cell_width;
+        let cell = match width {
+            CellWidth::Bits8 => "i8",
+            CellWidth::Bits16 => "i16",
+            CellWidth::Bits32 => "i32",
+        };
+        let mut f = Function { body : String::new(), cell, next_value : 0, next_label : 0 };
+        let mut loop_stack : Vec<usize> = vec![];
+
+        f.label("entry");
+        f.line("%ptr = alloca i64");
+        f.line(&format!("store i64 {}, ptr %ptr", self.settings.start_ptr()));
+
+        for token in data {
+            match token.token_type {
+                IncrementPtr(x) => f.move_ptr(x as isize),
+                DecrementPtr(x) => f.move_ptr(-(x as isize)),
+                IncrementData(x) | DecrementData(x) => {
+                    let op = if let IncrementData(_) = token.token_type { "add" } else { "sub" };
+                    let address = f.cell_address(0);
+                    let value = f.load(&address);
+                    let result = f.value();
+                    f.line(&format!("{} = {} {} {}, {}", result, op, cell, value, width.wrap(x as i64)));
+                    f.store(&address, &result);
+                },
+                Output => {
+                    let address = f.cell_address(0);
+                    let value = f.load(&address);
+                    let c = if width == CellWidth::Bits32 {
+                        value
+                    } else {
+                        let c = f.value();
+                        f.line(&format!("{} = zext {} {} to i32", c, cell, value));
+                        c
+                    };
+                    let ignored = f.value();
+                    f.line(&format!("{} = call i32 @putchar(i32 {})", ignored, c));
+                },
+                Input => {
+                    // Flush first so prompts are visible before blocking on input
+                    let ignored = f.value();
+                    f.line(&format!("{} = call i32 @fflush(ptr null)", ignored));
+                    let c = f.value();
+                    f.line(&format!("{} = call i32 @getchar()", c));
+                    let eof = f.value();
+                    f.line(&format!("{} = icmp eq i32 {}, -1", eof, c));
+                    let byte = if width == CellWidth::Bits32 {
+                        c
+                    } else {
+                        let byte = f.value();
+                        f.line(&format!("{} = trunc i32 {} to {}", byte, c, cell));
+                        byte
+                    };
+                    let address = f.cell_address(0);
+                    let value = match self.settings.eof {
+                        // -1 truncated is already all ones
+                        EofBehaviour::MinusOne => byte,
+                        EofBehaviour::Zero => {
+                            let value = f.value();
+                            f.line(&format!("{} = select i1 {}, {} 0, {} {}", value, eof, cell, cell, byte));
+                            value
+                        },
+                        EofBehaviour::Unchanged => {
+                            let old = f.load(&address);
+                            let value = f.value();
+                            f.line(&format!("{} = select i1 {}, {} {}, {} {}", value, eof, cell, old, cell, byte));
+                            value
+                        }
+                    };
+                    f.store(&address, &value);
+                },
+                LoopStart(_) => {
+                    f.next_label += 1;
+                    let label = f.next_label;
+                    loop_stack.push(label);
+                    f.line(&format!("br label %loop{}.cond", label));
+                    f.label(&format!("loop{}.cond", label));
+                    let address = f.cell_address(0);
+                    let value = f.load(&address);
+                    let test = f.value();
+                    f.line(&format!("{} = icmp ne {} {}, 0", test, cell, value));
+                    f.line(&format!("br i1 {}, label %loop{}.body, label %loop{}.end", test, label, label));
+                    f.label(&format!("loop{}.body", label));
+                },
+                LoopEnd(_) => {
+                    let label = match loop_stack.pop() {
+                        Some(label) => label,
+                        None => return CompileResult::Error { message : format!("Unmatched loop end at token {}", token.pos) }
+                    };
+                    f.line(&format!("br label %loop{}.cond", label));
+                    f.label(&format!("loop{}.end", label));
+                },
+                SetBlock(x, y) => {
+                    let value = width.wrap(y as i64).to_string();
+                    for i in 0..x {
+                        let address = f.cell_address(i as isize);
+                        f.store(&address, &value);
+                    }
+                    if x > 1 {
+                        f.move_ptr((x - 1) as isize);
+                    }
+                },
+                AddCurrentUp(x) | AddCurrentDown(x) => {
+                    let offset = if let AddCurrentUp(_) = token.token_type { x as isize } else { -(x as isize) };
+                    let current = f.cell_address(0);
+                    let value = f.load(&current);
+                    let target = f.cell_address(offset);
+                    let old = f.load(&target);
+                    let sum = f.value();
+                    f.line(&format!("{} = add {} {}, {}", sum, cell, old, value));
+                    f.store(&target, &sum);
+                    f.store(&current, "0");
+                },
+
+                // Removed by bf_compiler::compile
+                Debug => unreachable!()
+            }
+        }
+
+        if !loop_stack.is_empty() {
+            return CompileResult::Error { message : String::from("Unmatched loop start") };
+        }
+
+        f.line("ret i32 0");
+
+        let mut out = String::new();
+        out.push_str("; Generated by bf-cli\n\n");
+        out.push_str(&format!("@tape = internal global [{} x {}] zeroinitializer\n\n", self.settings.tape_size, cell));
+        out.push_str("declare i32 @getchar()\n");
+        out.push_str("declare i32 @putchar(i32)\n");
+        out.push_str("declare i32 @fflush(ptr)\n\n");
+        out.push_str("define i32 @main() {\n");
+        out.push_str(&f.body);
+        out.push_str("}\n");
+
+        CompileResult::Success(out.into_bytes())
+    }
+}
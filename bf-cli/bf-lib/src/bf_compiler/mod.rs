@@ -0,0 +1,135 @@
+// Ahead-of-time compilers
+//  Each backend translates the optimised token stream from
+//  BFLexer into a standalone program for another toolchain.
+//
+//  Programs should be compiled through compile below rather
+//  than a backend's own compile. '#' debug output only
+//  exists in the interpreters, so Debug tokens are dropped
+//  there and the backends never see them.
+
+use traits::*;
+use bf_lexer::{BFToken, BFTokenType};
+
+// C source backend
+pub mod c;
+// Rust source backend
+pub mod rust;
+// x86-64 assembly backend
+pub mod asm;
+// Linux x86-64 executable backend
+pub mod elf;
+// WebAssembly backend
+pub mod wasm;
+// LLVM IR backend
+pub mod llvm;
+
+// Size of each memory cell in the generated program.
+#[derive(PartialEq, Clone, Debug, Copy)]
+pub enum CellWidth {
+    Bits8,
+    Bits16,
+    Bits32,
+}
+
+impl CellWidth {
+    // Parses a width given in bits (8, 16 or 32).
+    pub fn from_bits(bits : &str) -> Option<CellWidth> {
+        match bits {
+            "8" => Some(CellWidth::Bits8),
+            "16" => Some(CellWidth::Bits16),
+            "32" => Some(CellWidth::Bits32),
+            _ => None
+        }
+    }
+
+    pub fn bits(&self) -> usize {
+        match *self {
+            CellWidth::Bits8 => 8,
+            CellWidth::Bits16 => 16,
+            CellWidth::Bits32 => 32,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bits() / 8
+    }
+
+    // Wraps a value to fit in a cell, so negative values
+    //  become their two's complement equivalent.
+    pub fn wrap(&self, value : i64) -> u64 {
+        (value as u64) & (u64::MAX >> (64 - self.bits()))
+    }
+}
+
+// What ',' does to the current cell once input has run out.
+#[derive(PartialEq, Clone, Debug, Copy)]
+pub enum EofBehaviour {
+    // Cell is set to 0 (this is what BFVM does)
+    Zero,
+    // Cell is set to -1
+    MinusOne,
+    // Cell is left as it was
+    Unchanged,
+}
+
+impl EofBehaviour {
+    pub fn from_name(name : &str) -> Option<EofBehaviour> {
+        match name {
+            "0" | "zero" => Some(EofBehaviour::Zero),
+            "-1" | "minus-one" => Some(EofBehaviour::MinusOne),
+            "unchanged" | "nochange" => Some(EofBehaviour::Unchanged),
+            _ => None
+        }
+    }
+}
+
+// Settings shared by all of the compiler backends.
+//  The defaults match the behaviour of BFVM.
+#[derive(Clone, Debug)]
+pub struct CompileSettings {
+
+    // Number of cells on the tape
+    pub tape_size : usize,
+
+    pub cell_width : CellWidth,
+
+    pub eof : EofBehaviour,
+}
+
+impl CompileSettings {
+    pub fn new() -> CompileSettings {
+        CompileSettings {
+            tape_size : 60000,
+            cell_width : CellWidth::Bits8,
+            eof : EofBehaviour::Zero,
+        }
+    }
+
+    // Like BFVM the data pointer starts in the middle
+    //  of the tape so programs can go backwards.
+    pub fn start_ptr(&self) -> usize {
+        self.tape_size / 2
+    }
+
+    // Checks the settings make sense before generating any code.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tape_size == 0 {
+            return Err(String::from("Tape size must be greater than 0"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for CompileSettings {
+    fn default() -> CompileSettings {
+        CompileSettings::new()
+    }
+}
+
+// Compiles the tokens with a backend, leaving out anything
+//  that only the interpreters support. Backends match loops
+//  up with a stack, so the loop targets in the tokens going
+//  stale when tokens are removed doesn't matter.
+pub fn compile<C : Compiler<BFToken>>(compiler : &C, tokens : Vec<BFToken>) -> CompileResult {
+    compiler.compile(tokens.into_iter().filter(|token| token.token_type != BFTokenType::Debug).collect())
+}
@@ -0,0 +1,165 @@
+use traits::*;
+use bf_lexer::*;
+use bf_compiler::*;
+
+// Translates BF tokens into a self-contained Rust
+//  source file.
+//
+// The program is emitted as a `run` function that is
+//  generic over its input and output, so the file can
+//  either be built as a binary or included in another
+//  crate as a module.
+pub struct RustCompiler {
+    settings : CompileSettings,
+
+    // Use get_unchecked instead of indexing the tape,
+    //  removing the bounds checks from every cell access.
+    pub unchecked : bool,
+
+    // Leave out the main function so the output can be
+    //  used as a module.
+    pub module : bool
+}
+
+impl RustCompiler {
+    pub fn new(settings : CompileSettings) -> RustCompiler {
+        RustCompiler {
+            settings,
+            unchecked : false,
+            module : false
+        }
+    }
+
+    fn cell_type(&self) -> &'static str {
+        match self.settings.cell_width {
+            CellWidth::Bits8 => "u8",
+            CellWidth::Bits16 => "u16",
+            CellWidth::Bits32 => "u32",
+        }
+    }
+
+    // Expression for the cell at the data pointer plus an offset.
+    fn cell(&self, offset : isize) -> String {
+        let index = if offset > 0 {
+            format!("p + {}", offset)
+        } else if offset < 0 {
+            format!("p - {}", -offset)
+        } else {
+            String::from("p")
+        };
+
+        self.cell_at(&index)
+    }
+
+    // Expression for the cell at the given tape index.
+    fn cell_at(&self, index : &str) -> String {
+        if self.unchecked {
+            format!("(*tape.get_unchecked_mut({}))", index)
+        } else {
+            format!("tape[{}]", index)
+        }
+    }
+}
+
+impl Compiler<BFToken> for RustCompiler {
+
+    fn compile(&self, data : Vec<BFToken>) -> CompileResult {
+        use bf_lexer::BFTokenType::*;
+
+        if let Err(message) = self.settings.validate() {
+            return CompileResult::Error { message };
+        }
+
+        let cell_type = self.cell_type();
+        let width = self.settings.cell_width;
+        let current = self.cell(0);
+        let mut out = String::new();
+
+        out.push_str("// Generated by bf-cli\n");
+        out.push_str("#![allow(unused)]\n\n");
+        out.push_str("use std::io::{self, Read, Write};\n\n");
+        out.push_str(&format!("pub const TAPE_SIZE : usize = {};\n\n", self.settings.tape_size));
+        out.push_str("pub fn run<R : Read, W : Write>(input : &mut R, output : &mut W) -> io::Result<()> {\n");
+        out.push_str(&format!("    let mut tape = vec![0{}; TAPE_SIZE];\n", cell_type));
+        out.push_str(&format!("    let mut p : usize = {};\n", self.settings.start_ptr()));
+        out.push_str("    let mut byte = [0u8; 1];\n");
+
+        let mut indent = String::from("    ");
+        if self.unchecked {
+            out.push_str("    unsafe {\n");
+            indent.push_str("    ");
+        }
+        let base = indent.len();
+
+        for token in data {
+            match token.token_type {
+                IncrementPtr(x) => out.push_str(&format!("{}p += {};\n", indent, x)),
+                DecrementPtr(x) => out.push_str(&format!("{}p -= {};\n", indent, x)),
+                IncrementData(x) => out.push_str(&format!("{}{} = {}.wrapping_add({});\n", indent, current, current, width.wrap(x as i64))),
+                DecrementData(x) => out.push_str(&format!("{}{} = {}.wrapping_sub({});\n", indent, current, current, width.wrap(x as i64))),
+                Output => out.push_str(&format!("{}output.write_all(&[{} as u8])?;\n", indent, current)),
+                Input => {
+                    // Flush first so prompts are visible before blocking on input
+                    out.push_str(&format!("{}output.flush()?;\n", indent));
+                    let eof = match self.settings.eof {
+                        EofBehaviour::Zero => format!("{} = 0", current),
+                        EofBehaviour::MinusOne => format!("{} = {}::MAX", current, cell_type),
+                        EofBehaviour::Unchanged => String::from("{}"),
+                    };
+                    out.push_str(&format!("{}if input.read(&mut byte)? == 0 {{ {} }} else {{ {} = byte[0] as {} }}\n",
+                        indent, eof, current, cell_type));
+                },
+                LoopStart(_) => {
+                    out.push_str(&format!("{}while {} != 0 {{\n", indent, current));
+                    indent.push_str("    ");
+                },
+                LoopEnd(_) => {
+                    if indent.len() <= base {
+                        return CompileResult::Error { message : format!("Unmatched loop end at token {}", token.pos) };
+                    }
+                    let len = indent.len() - 4;
+                    indent.truncate(len);
+                    out.push_str(&format!("{}}}\n", indent));
+                },
+                SetBlock(x, y) => {
+                    out.push_str(&format!("{}for i in 0..{} {{ {} = {}; }}\n", indent, x, self.cell_at("p + i"), width.wrap(y as i64)));
+                    if x > 1 {
+                        out.push_str(&format!("{}p += {};\n", indent, x - 1));
+                    }
+                },
+                AddCurrentUp(x) => {
+                    let target = self.cell(x as isize);
+                    out.push_str(&format!("{}{} = {}.wrapping_add({});\n{}{} = 0;\n", indent, target, target, current, indent, current));
+                },
+                AddCurrentDown(x) => {
+                    let target = self.cell(-(x as isize));
+                    out.push_str(&format!("{}{} = {}.wrapping_add({});\n{}{} = 0;\n", indent, target, target, current, indent, current));
+                },
+
+                // Removed by bf_compiler::compile
+                Debug => unreachable!()
+            }
+        }
+
+        if indent.len() != base {
+            return CompileResult::Error { message : String::from("Unmatched loop start") };
+        }
+
+        if self.unchecked {
+            out.push_str("    }\n");
+        }
+        out.push_str("    output.flush()\n");
+        out.push_str("}\n");
+
+        if !self.module {
+            out.push_str("\nfn main() {\n");
+            out.push_str("    let stdin = io::stdin();\n");
+            out.push_str("    let stdout = io::stdout();\n");
+            out.push_str("    let mut output = io::BufWriter::new(stdout.lock());\n");
+            out.push_str("    run(&mut stdin.lock(), &mut output).expect(\"I/O error\");\n");
+            out.push_str("}\n");
+        }
+
+        CompileResult::Success(out.into_bytes())
+    }
+}
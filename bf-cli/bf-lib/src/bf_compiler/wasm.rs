@@ -0,0 +1,407 @@
+use traits::*;
+use bf_lexer::*;
+use bf_compiler::*;
+
+// Translates BF tokens into a WebAssembly module, either
+//  as .wat text or as a binary .wasm file.
+//
+// The module imports two functions from "env":
+//  getc() -> i32       Returns the next input byte, or -1 at the end of input
+//  putc(i32)           Writes the low byte of its argument
+//
+// It exports its linear memory as "memory", which holds the
+//  tape starting at address 0, and the program as "run".
+pub struct WasmCompiler {
+    settings : CompileSettings,
+
+    // Output .wat text instead of a binary module
+    pub text : bool
+}
+
+// Function indices - imports come first
+const GETC : u32 = 0;
+const PUTC : u32 = 1;
+const RUN : u32 = 2;
+
+// Local indices
+const PTR : u32 = 0;
+const VALUE : u32 = 1;
+
+// Size of a linear memory page
+const PAGE_SIZE : usize = 65536;
+
+// The subset of WebAssembly instructions the compiler uses.
+//  Programs are built from these first and then written
+//  out as text or binary, so both forms always match.
+#[derive(Clone, Debug, Copy)]
+enum Ins {
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Call(u32),
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    Const(i32),
+    Eqz,
+    LtS,
+    Add,
+    Sub,
+    // Cell sized load/store with a constant offset
+    Load(u32),
+    Store(u32),
+}
+
+impl WasmCompiler {
+    pub fn new(settings : CompileSettings) -> WasmCompiler {
+        WasmCompiler {
+            settings,
+            text : false
+        }
+    }
+
+    // Pushes the address of the cell at the data pointer plus
+    //  an offset, returning the constant offset to use when
+    //  loading or storing.
+    fn address(&self, code : &mut Vec<Ins>, offset : isize) -> u32 {
+        let bytes = offset * self.settings.cell_width.bytes() as isize;
+        code.push(Ins::LocalGet(PTR));
+        if bytes < 0 {
+            code.push(Ins::Const(-bytes as i32));
+            code.push(Ins::Sub);
+            0
+        } else {
+            bytes as u32
+        }
+    }
+
+    // Builds the body of the run function.
+    fn instructions(&self, data : Vec<BFToken>) -> Result<Vec<Ins>, String> {
+        use bf_lexer::BFTokenType::*;
+        use self::Ins::*;
+
+        let width = self.settings.cell_width;
+        let bytes = width.bytes();
+        let mut code : Vec<Ins> = vec![];
+        let mut depth = 0;
+
+        code.push(Const((self.settings.start_ptr() * bytes) as i32));
+        code.push(LocalSet(PTR));
+
+        for token in data {
+            match token.token_type {
+                IncrementPtr(x) | DecrementPtr(x) => {
+                    code.push(LocalGet(PTR));
+                    code.push(Const((x * bytes) as i32));
+                    code.push(if let IncrementPtr(_) = token.token_type { Add } else { Sub });
+                    code.push(LocalSet(PTR));
+                },
+                IncrementData(x) | DecrementData(x) => {
+                    code.push(LocalGet(PTR));
+                    code.push(LocalGet(PTR));
+                    code.push(Load(0));
+                    code.push(Const(width.wrap(x as i64) as u32 as i32));
+                    code.push(if let IncrementData(_) = token.token_type { Add } else { Sub });
+                    code.push(Store(0));
+                },
+                Output => {
+                    code.push(LocalGet(PTR));
+                    code.push(Load(0));
+                    code.push(Call(PUTC));
+                },
+                Input => {
+                    code.push(Call(GETC));
+                    code.push(LocalTee(VALUE));
+                    code.push(Const(0));
+                    code.push(LtS);
+                    code.push(If);
+                    match self.settings.eof {
+                        EofBehaviour::Zero => code.extend_from_slice(&[LocalGet(PTR), Const(0), Store(0)]),
+                        EofBehaviour::MinusOne => code.extend_from_slice(&[LocalGet(PTR), Const(-1), Store(0)]),
+                        EofBehaviour::Unchanged => { }
+                    }
+                    code.push(Else);
+                    code.extend_from_slice(&[LocalGet(PTR), LocalGet(VALUE), Store(0)]);
+                    code.push(End);
+                },
+                LoopStart(_) => {
+                    // block
+                    //   loop
+                    //     br_if 1 (cell == 0)
+                    //     ...
+                    //     br 0
+                    //   end
+                    // end
+                    depth += 1;
+                    code.push(Block);
+                    code.push(Loop);
+                    code.push(LocalGet(PTR));
+                    code.push(Load(0));
+                    code.push(Eqz);
+                    code.push(BrIf(1));
+                },
+                LoopEnd(_) => {
+                    if depth == 0 {
+                        return Err(format!("Unmatched loop end at token {}", token.pos));
+                    }
+                    depth -= 1;
+                    code.push(Br(0));
+                    code.push(End);
+                    code.push(End);
+                },
+                SetBlock(x, y) => {
+                    for i in 0..x {
+                        code.push(LocalGet(PTR));
+                        code.push(Const(width.wrap(y as i64) as u32 as i32));
+                        code.push(Store((i * bytes) as u32));
+                    }
+                    if x > 1 {
+                        code.push(LocalGet(PTR));
+                        code.push(Const(((x - 1) * bytes) as i32));
+                        code.push(Add);
+                        code.push(LocalSet(PTR));
+                    }
+                },
+                AddCurrentUp(x) | AddCurrentDown(x) => {
+                    let offset = if let AddCurrentUp(_) = token.token_type { x as isize } else { -(x as isize) };
+                    let store = self.address(&mut code, offset);
+                    let load = self.address(&mut code, offset);
+                    code.push(Load(load));
+                    code.push(LocalGet(PTR));
+                    code.push(Load(0));
+                    code.push(Add);
+                    code.push(Store(store));
+                    code.extend_from_slice(&[LocalGet(PTR), Const(0), Store(0)]);
+                },
+
+                // Removed by bf_compiler::compile
+                Debug => unreachable!()
+            }
+        }
+
+        if depth != 0 {
+            return Err(String::from("Unmatched loop start"));
+        }
+
+        Ok(code)
+    }
+
+    fn pages(&self) -> usize {
+        let bytes = self.settings.tape_size * self.settings.cell_width.bytes();
+        bytes.div_ceil(PAGE_SIZE)
+    }
+
+    fn load_name(&self) -> &'static str {
+        match self.settings.cell_width {
+            CellWidth::Bits8 => "i32.load8_u",
+            CellWidth::Bits16 => "i32.load16_u",
+            CellWidth::Bits32 => "i32.load",
+        }
+    }
+
+    fn store_name(&self) -> &'static str {
+        match self.settings.cell_width {
+            CellWidth::Bits8 => "i32.store8",
+            CellWidth::Bits16 => "i32.store16",
+            CellWidth::Bits32 => "i32.store",
+        }
+    }
+
+    // Writes the module out as .wat text.
+    fn write_text(&self, code : &[Ins]) -> Vec<u8> {
+        let mut out = String::new();
+        out.push_str(";; Generated by bf-cli\n");
+        out.push_str("(module\n");
+        out.push_str("  (import \"env\" \"getc\" (func $getc (result i32)))\n");
+        out.push_str("  (import \"env\" \"putc\" (func $putc (param i32)))\n");
+        out.push_str(&format!("  (memory (export \"memory\") {})\n", self.pages()));
+        out.push_str("  (func (export \"run\")\n");
+        out.push_str("    (local $p i32)\n");
+        out.push_str("    (local $v i32)\n");
+
+        let local = |index : u32| if index == PTR { "$p" } else { "$v" };
+        let memarg = |offset : u32| if offset == 0 { String::new() } else { format!(" offset={}", offset) };
+
+        let mut indent = String::from("    ");
+        for ins in code {
+            if let Ins::End | Ins::Else = *ins {
+                indent.truncate(indent.len() - 2);
+            }
+            let line = match *ins {
+                Ins::Block => String::from("block"),
+                Ins::Loop => String::from("loop"),
+                Ins::If => String::from("if"),
+                Ins::Else => String::from("else"),
+                Ins::End => String::from("end"),
+                Ins::Br(depth) => format!("br {}", depth),
+                Ins::BrIf(depth) => format!("br_if {}", depth),
+                Ins::Call(func) => format!("call {}", if func == GETC { "$getc" } else { "$putc" }),
+                Ins::LocalGet(index) => format!("local.get {}", local(index)),
+                Ins::LocalSet(index) => format!("local.set {}", local(index)),
+                Ins::LocalTee(index) => format!("local.tee {}", local(index)),
+                Ins::Const(value) => format!("i32.const {}", value),
+                Ins::Eqz => String::from("i32.eqz"),
+                Ins::LtS => String::from("i32.lt_s"),
+                Ins::Add => String::from("i32.add"),
+                Ins::Sub => String::from("i32.sub"),
+                Ins::Load(offset) => format!("{}{}", self.load_name(), memarg(offset)),
+                Ins::Store(offset) => format!("{}{}", self.store_name(), memarg(offset)),
+            };
+            out.push_str(&format!("{}{}\n", indent, line));
+            if let Ins::Block | Ins::Loop | Ins::If | Ins::Else = *ins {
+                indent.push_str("  ");
+            }
+        }
+
+        out.push_str("  )\n");
+        out.push_str(")\n");
+        out.into_bytes()
+    }
+
+    // Writes the module out in the binary format.
+    fn write_binary(&self, code : &[Ins]) -> Vec<u8> {
+        // Alignment hint for loads and stores, as a power of 2
+        let align = match self.settings.cell_width {
+            CellWidth::Bits8 => 0,
+            CellWidth::Bits16 => 1,
+            CellWidth::Bits32 => 2,
+        };
+        let (load, store) = match self.settings.cell_width {
+            CellWidth::Bits8 => (0x2D, 0x3A),
+            CellWidth::Bits16 => (0x2F, 0x3B),
+            CellWidth::Bits32 => (0x28, 0x36),
+        };
+
+        let mut body : Vec<u8> = vec![];
+        // One local declaration: 2 x i32
+        body.extend_from_slice(&[1, 2, 0x7F]);
+        for ins in code {
+            match *ins {
+                Ins::Block => body.extend_from_slice(&[0x02, 0x40]),
+                Ins::Loop => body.extend_from_slice(&[0x03, 0x40]),
+                Ins::If => body.extend_from_slice(&[0x04, 0x40]),
+                Ins::Else => body.push(0x05),
+                Ins::End => body.push(0x0B),
+                Ins::Br(depth) => { body.push(0x0C); leb_u32(&mut body, depth); },
+                Ins::BrIf(depth) => { body.push(0x0D); leb_u32(&mut body, depth); },
+                Ins::Call(func) => { body.push(0x10); leb_u32(&mut body, func); },
+                Ins::LocalGet(index) => { body.push(0x20); leb_u32(&mut body, index); },
+                Ins::LocalSet(index) => { body.push(0x21); leb_u32(&mut body, index); },
+                Ins::LocalTee(index) => { body.push(0x22); leb_u32(&mut body, index); },
+                Ins::Const(value) => { body.push(0x41); leb_i32(&mut body, value); },
+                Ins::Eqz => body.push(0x45),
+                Ins::LtS => body.push(0x48),
+                Ins::Add => body.push(0x6A),
+                Ins::Sub => body.push(0x6B),
+                Ins::Load(offset) => { body.push(load); body.push(align); leb_u32(&mut body, offset); },
+                Ins::Store(offset) => { body.push(store); body.push(align); leb_u32(&mut body, offset); },
+            }
+        }
+        body.push(0x0B);
+
+        let mut out : Vec<u8> = vec![];
+        out.extend_from_slice(b"\0asm");
+        out.extend_from_slice(&1u32.to_le_bytes());
+
+        // Types: () -> i32, (i32) -> (), () -> ()
+        section(&mut out, 1, &[3, 0x60, 0, 1, 0x7F, 0x60, 1, 0x7F, 0, 0x60, 0, 0]);
+
+        // Imports
+        let mut imports : Vec<u8> = vec![2];
+        import(&mut imports, "getc", 0);
+        import(&mut imports, "putc", 1);
+        section(&mut out, 2, &imports);
+
+        // Functions - run has type 2
+        section(&mut out, 3, &[1, 2]);
+
+        // Memory - one with no maximum
+        let mut memory : Vec<u8> = vec![1, 0];
+        leb_u32(&mut memory, self.pages() as u32);
+        section(&mut out, 5, &memory);
+
+        // Exports
+        let mut exports : Vec<u8> = vec![2];
+        name(&mut exports, "memory");
+        exports.extend_from_slice(&[0x02, 0]);
+        name(&mut exports, "run");
+        exports.push(0x00);
+        leb_u32(&mut exports, RUN);
+        section(&mut out, 7, &exports);
+
+        // Code
+        let mut code_section : Vec<u8> = vec![1];
+        leb_u32(&mut code_section, body.len() as u32);
+        code_section.extend_from_slice(&body);
+        section(&mut out, 10, &code_section);
+
+        out
+    }
+}
+
+// Unsigned LEB128
+fn leb_u32(out : &mut Vec<u8>, mut value : u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// Signed LEB128
+fn leb_i32(out : &mut Vec<u8>, mut value : i32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(out : &mut Vec<u8>, name : &str) {
+    leb_u32(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn import(out : &mut Vec<u8>, func : &str, type_index : u8) {
+    name(out, "env");
+    name(out, func);
+    out.extend_from_slice(&[0x00, type_index]);
+}
+
+fn section(out : &mut Vec<u8>, id : u8, contents : &[u8]) {
+    out.push(id);
+    leb_u32(out, contents.len() as u32);
+    out.extend_from_slice(contents);
+}
+
+impl Compiler<BFToken> for WasmCompiler {
+
+    fn compile(&self, data : Vec<BFToken>) -> CompileResult {
+        if let Err(message) = self.settings.validate() {
+            return CompileResult::Error { message };
+        }
+
+        match self.instructions(data) {
+            Ok(code) => {
+                if self.text {
+                    CompileResult::Success(self.write_text(&code))
+                } else {
+                    CompileResult::Success(self.write_binary(&code))
+                }
+            },
+            Err(message) => CompileResult::Error { message }
+        }
+    }
+}
@@ -18,7 +18,11 @@ pub enum BFTokenType {
     // Optimised instructions
     SetBlock(usize,i8),        // Sets blocks of sells to value
     AddCurrentUp(usize),      // Adds current cell to cell in [current + value]
-    AddCurrentDown(usize)     // Adds current cell to cell in [current - value]
+    AddCurrentDown(usize),    // Adds current cell to cell in [current - value]
+
+    // Only with LexSettings.debug_token, prints the cells
+    //  around the data pointer to stderr.
+    Debug                     // #
 }
 
 
@@ -27,15 +31,50 @@ pub struct BFToken {
     pub token_type : BFTokenType,
 
     // Used for matching brackets
-    pub pos : usize
+    pub pos : usize,
+
+    // Character in the source the token starts at, for
+    //  merged tokens this is the first one's.
+    pub source_pos : usize,
+
+    // Number of characters from source_pos to the end of
+    //  the token, covering everything merged into it.
+    pub source_len : usize
 }
 
 pub struct BFLexer {
 }
 
+// Optional extensions to the language
+pub struct LexSettings {
+    // Treat '#' as a Debug token instead of a comment
+    pub debug_token : bool
+}
+
+impl LexSettings {
+    pub fn new() -> LexSettings {
+        LexSettings {
+            debug_token : false
+        }
+    }
+}
+
+impl Default for LexSettings {
+    fn default() -> LexSettings {
+        LexSettings::new()
+    }
+}
+
 impl Lexer<Vec<BFToken>> for BFLexer {
 
     fn parse(input_string:String) -> LexResult<Vec<BFToken>> {
+        BFLexer::parse_with(input_string, &LexSettings::new())
+    }
+}
+
+impl BFLexer {
+
+    pub fn parse_with(input_string:String, settings : &LexSettings) -> LexResult<Vec<BFToken>> {
         // Import enum -> Allows for using enum values without
         //  BFTokenType:: prefix
         use self::BFTokenType::*;
@@ -51,12 +90,20 @@ impl Lexer<Vec<BFToken>> for BFLexer {
         //  so that they can jump to each other in O(1)
         let mut loop_stack : Vec<usize> = vec![];
 
+        // Character positions of the open brackets, used
+        //  to report any that are never closed.
+        let mut loop_chars : Vec<usize> = vec![];
+
         // Store the previous 5 tokens, these are used
         //  for optimisations.
-        let mut last_tokens = [BFToken { pos : 0, token_type : Input }; 5];
+        let mut last_tokens = [BFToken { pos : 0, token_type : Input, source_pos : 0, source_len : 0 }; 5];
 
         // Loop through each character
-        for character in input_string.chars() {
+        for (char_pos, character) in input_string.chars().enumerate() {
+            // Moves back to the start of any tokens merged into
+            //  this one.
+            let mut source_pos = char_pos;
+
             // Match our character to a TokenType.
             let token_type = match character {
                 '>' => {
@@ -64,7 +111,7 @@ impl Lexer<Vec<BFToken>> for BFLexer {
                     let mut new_x = 1;
                     if last_tokens[0].pos > 0 {
                         if let IncrementPtr(x) = last_tokens[0].token_type {
-                            tokens.pop();
+                            source_pos = tokens.pop().unwrap().source_pos;
                             pos-=1;
                             new_x += x;
                         }
@@ -75,7 +122,7 @@ impl Lexer<Vec<BFToken>> for BFLexer {
                     let mut new_x = 1;
                     if last_tokens[0].pos > 0 {
                         if let DecrementPtr(x) = last_tokens[0].token_type {
-                            tokens.pop();
+                            source_pos = tokens.pop().unwrap().source_pos;
                             pos-=1;                             
                             new_x += x;                           
                         }
@@ -86,7 +133,7 @@ impl Lexer<Vec<BFToken>> for BFLexer {
                     let mut new_x = 1;
                     if last_tokens[0].pos > 0 {
                         if let IncrementData(x) = last_tokens[0].token_type {
-                            tokens.pop();
+                            source_pos = tokens.pop().unwrap().source_pos;
                             pos-=1;
                             new_x += x;
                         }
@@ -97,7 +144,7 @@ impl Lexer<Vec<BFToken>> for BFLexer {
                     let mut new_x = 1;
                     if last_tokens[0].pos > 0 {
                         if let DecrementData(x) = last_tokens[0].token_type {
-                            tokens.pop();
+                            source_pos = tokens.pop().unwrap().source_pos;
                             pos-=1;
                             new_x += x;
                         }
@@ -106,9 +153,11 @@ impl Lexer<Vec<BFToken>> for BFLexer {
                 },
                 '.' => Output,
                 ',' => Input,
+                '#' if settings.debug_token => Debug,
                 '[' => {
                     // Push this pos onto stack
                     loop_stack.push(pos);
+                    loop_chars.push(char_pos);
 
                     // Store temp value of 0 for now - will be updated
                     //  once we know matching ]
@@ -117,7 +166,11 @@ impl Lexer<Vec<BFToken>> for BFLexer {
                 ']' => {
 
                     // Get matching bracket.
-                    let index = loop_stack.pop().expect("Bracket mismatch");
+                    let index = match loop_stack.pop() {
+                        Some(index) => index,
+                        None => return LexResult::Error { message : String::from("Unmatched ']'"), pos : char_pos }
+                    };
+                    loop_chars.pop();
                     let mut ret_token : BFTokenType = LoopEnd(index);      
 
                     // Update it's data position to this ] token
@@ -135,21 +188,25 @@ impl Lexer<Vec<BFToken>> for BFLexer {
                         IncrementData(_) 
                         | DecrementData(_) => {
                             if let LoopStart(_) = last_tokens[1].token_type {
+                                // Replace the '[' and '-'
                                 let mut size = 1;
-                                // Check for blocks
+                                tokens.pop();
+                                source_pos = tokens.pop().unwrap().source_pos;
+                                pos-=2;
+
+                                // Check for blocks, the '>' and the SetBlock
+                                //  before it are merged in too, so this
+                                //  starts where that SetBlock did.
                                 if let IncrementPtr(1) = last_tokens[2].token_type {
                                     if let SetBlock(x, 0) = last_tokens[3].token_type {
                                         size = x + 1;
                                         tokens.pop();
-                                        tokens.pop();
+                                        source_pos = tokens.pop().unwrap().source_pos;
                                         pos-=2;
                                     };
                                 }
 
                                 ret_token = SetBlock(size, 0);
-                                tokens.pop();
-                                tokens.pop();
-                                pos-=2;
                             }
                         },
 
@@ -179,23 +236,21 @@ impl Lexer<Vec<BFToken>> for BFLexer {
                             if let IncrementData(a) = last_tokens[1].token_type {
                                 match last_tokens[2].token_type {
                                     IncrementPtr(y)
-                                    | DecrementPtr(y) => {
-                                        if x == y {
-                                            if let DecrementData(b) = last_tokens[3].token_type  {
-                                                if a == b {
-                                                    if let LoopStart(_) = last_tokens[4].token_type {
-                                                        if last_tokens[0].token_type == DecrementPtr(x) {
-                                                            ret_token = AddCurrentUp(x);
-                                                        } else {
-                                                            ret_token = AddCurrentDown(x);
-                                                        }
-                                                        tokens.pop();
-                                                        tokens.pop();
-                                                        tokens.pop();
-                                                        tokens.pop();
-                                                        tokens.pop();
-                                                        pos-=5;
+                                    | DecrementPtr(y) if x == y => {
+                                        if let DecrementData(b) = last_tokens[3].token_type  {
+                                            if a == b {
+                                                if let LoopStart(_) = last_tokens[4].token_type {
+                                                    if last_tokens[0].token_type == DecrementPtr(x) {
+                                                        ret_token = AddCurrentUp(x);
+                                                    } else {
+                                                        ret_token = AddCurrentDown(x);
                                                     }
+                                                    tokens.pop();
+                                                    tokens.pop();
+                                                    tokens.pop();
+                                                    tokens.pop();
+                                                    source_pos = tokens.pop().unwrap().source_pos;
+                                                    pos-=5;
                                                 }
                                             }
                                         }
@@ -227,21 +282,24 @@ impl Lexer<Vec<BFToken>> for BFLexer {
             // Recopy last tokens instead of shifting as they 
             //  may have changed due to optimisations.
             while i < 5 && pos > i-1 {
-                last_tokens[i] = tokens[pos-i].clone();
+                last_tokens[i] = tokens[pos-i];
                 i+=1;
             }
 
             pos+=1;
 
             // Add it to the list of tokens
-            let token = BFToken { token_type : token_type, pos : pos};
+            let token = BFToken { token_type, pos, source_pos, source_len : char_pos + 1 - source_pos };
 
-            last_tokens[0] = token.clone();
+            last_tokens[0] = token;
 
             tokens.push(token);
         }
 
-        // No way for this to currently fail.
+        if let Some(char_pos) = loop_chars.pop() {
+            return LexResult::Error { message : String::from("Unmatched '['"), pos : char_pos };
+        }
+
         LexResult::Success(tokens)
     }
 }
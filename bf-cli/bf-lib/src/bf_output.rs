@@ -1,8 +1,22 @@
 use bf_lexer::*;
 use std::fs::File;
+use std::io;
 use std::io::Write;
 
-pub fn dump_tokens(tokens : Vec<BFToken>, file_name : String) {
+// Prints the dump format and writes it to a file, failing
+//  if the file can't be created or written to.
+pub fn dump_tokens(tokens : Vec<BFToken>, file_name : String) -> io::Result<()> {
+    let token_string = format_tokens(tokens);
+
+    let mut file = File::create(&file_name)?;
+    file.write_all(token_string.as_bytes())?;
+
+    println!("Dumped to '{}':\n{}", file_name, token_string);
+    Ok(())
+}
+
+// Formats the tokens in the optimised dump format.
+pub fn format_tokens(tokens : Vec<BFToken>) -> String {
     use bf_lexer::BFTokenType::*;
 
     let mut token_string : String = String::new();
@@ -26,12 +40,144 @@ pub fn dump_tokens(tokens : Vec<BFToken>, file_name : String) {
             },
             SetBlock(x, y) => token_string = format!("{}\n{}{}", token_string, indent, (format!("\tset {} {}", x, y))),
             AddCurrentUp(x) => token_string = format!("{}\n{}{}", token_string, indent, (format!("\taddc {}", x))),
-            AddCurrentDown(x) => token_string = format!("{}\n{}{}", token_string, indent, (format!("\taddc -{}", x)))
+            AddCurrentDown(x) => token_string = format!("{}\n{}{}", token_string, indent, (format!("\taddc -{}", x))),
+            Debug => token_string = format!("{}\n{}{}", token_string, indent, "\tdebug")
+        }
+    }
+
+    token_string
+}
+// Reformats BF source code, putting each loop on its own
+//  indented lines. Short loops with no nested loops inside
+//  are kept on one line. Anything that isn't a BF command
+//  (including comments) is dropped.
+pub fn format_source(input : &str, width : usize) -> String {
+    let commands : Vec<char> = input.chars().filter(|c| "<>+-.,[]".contains(*c)).collect();
+
+    let mut out = String::new();
+    let mut line = String::new();
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < commands.len() {
+        let indent = depth * 2;
+        match commands[i] {
+            '[' => {
+                // Find the matching ] if the loop is simple enough to keep inline
+                let inline_end = commands[i + 1..].iter().position(|c| *c == '[' || *c == ']')
+                    .map(|offset| i + 1 + offset)
+                    .filter(|end| commands[*end] == ']' && *end - i < width / 2);
+
+                if let Some(end) = inline_end {
+                    if indent + line.len() + end - i + 1 > width {
+                        flush_line(&mut out, &mut line, indent);
+                    }
+                    line.extend(&commands[i..end + 1]);
+                    i = end + 1;
+                    continue;
+                }
+
+                flush_line(&mut out, &mut line, indent);
+                line.push('[');
+                flush_line(&mut out, &mut line, indent);
+                depth += 1;
+            },
+            ']' => {
+                flush_line(&mut out, &mut line, indent);
+                depth = if depth > 0 { depth - 1 } else { 0 };
+                line.push(']');
+                flush_line(&mut out, &mut line, depth * 2);
+            },
+            c => {
+                if indent + line.len() >= width {
+                    flush_line(&mut out, &mut line, indent);
+                }
+                line.push(c);
+            }
         }
+        i += 1;
+    }
+
+    flush_line(&mut out, &mut line, depth * 2);
+    out
+}
+
+fn flush_line(out : &mut String, line : &mut String, indent : usize) {
+    if !line.is_empty() {
+        out.push_str(&" ".repeat(indent));
+        out.push_str(line);
+        out.push('\n');
+        line.clear();
     }
+}
+
+// Shows the cells either side of the data pointer, with the
+//  current cell marked:
+//
+//  cell   29998 29999 30000 30001
+//  value      0     0    72   101
+//  char       .     .     H     e
+//                         ^
+pub fn format_tape_window(mem : &[i8], data_ptr : usize, radius : usize) -> String {
+    let start = data_ptr.saturating_sub(radius);
+    let end = (data_ptr + radius + 1).min(mem.len());
+
+    let mut cells = String::from("cell  ");
+    let mut values = String::from("value ");
+    let mut chars = String::from("char  ");
+    let mut marker = String::from("      ");
+
+    for (i, cell) in mem.iter().enumerate().take(end).skip(start) {
+        let byte = *cell as u8;
+        let c = if byte.is_ascii_graphic() { byte as char } else { '.' };
+        cells.push_str(&format!("{:>6}", i));
+        values.push_str(&format!("{:>6}", byte));
+        chars.push_str(&format!("{:>6}", c));
+        marker.push_str(if i == data_ptr { "     ^" } else { "      " });
+    }
+
+    format!("{}\n{}\n{}\n{}", cells, values, chars, marker.trim_end())
+}
+
+// Dumps the used part of the tape, from the first to the
+//  last non-zero cell (always including the data pointer),
+//  eight cells to a row as hex, decimal and characters. The
+//  data pointer's cell is marked with a '>':
+//
+//   29992  00 00 00 00 00 00 00 00    0   0   0   0   0   0   0   0  ........
+//   30000 >48 65 6c 6c 00 00 00 00   72 101 108 108   0   0   0   0  Hell....
+//
+//  data pointer: 30000
+pub fn format_tape_dump(mem : &[i8], data_ptr : usize) -> String {
+    const ROW : usize = 8;
 
-    println!("Dumping to '{}':\n{}", file_name, token_string);
+    let first = mem.iter().position(|cell| *cell != 0).unwrap_or(data_ptr).min(data_ptr);
+    let last = mem.iter().rposition(|cell| *cell != 0).unwrap_or(data_ptr).max(data_ptr);
+
+    let mut out = String::new();
+    let mut row = first - first % ROW;
+    while row <= last {
+        let cells = &mem[row..(row + ROW).min(mem.len())];
+
+        out.push_str(&format!("{:>6} ", row));
+        for (i, cell) in cells.iter().enumerate() {
+            let marker = if row + i == data_ptr { '>' } else { ' ' };
+            out.push_str(&format!("{}{:02x}", marker, *cell as u8));
+        }
+        out.push_str("  ");
+        for cell in cells {
+            out.push_str(&format!(" {:>3}", *cell as u8));
+        }
+        out.push_str("  ");
+        for cell in cells {
+            let byte = *cell as u8;
+            out.push(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' });
+        }
+        out.push('\n');
+
+        row += ROW;
+    }
 
-    let mut file = File::create(file_name).expect("Unable to create dump file.");
-    file.write_all(token_string.as_bytes()).expect("Unable to write to dump file.");
-}
\ No newline at end of file
+    out.push_str(&format!("data pointer: {}", data_ptr));
+    out
+}
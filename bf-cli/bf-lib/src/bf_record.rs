@@ -0,0 +1,91 @@
+use std::io::Write;
+
+// Recording and replaying program input.
+//
+// Every byte read by ',' is logged along with the number of
+//  instructions run before it was read, so a run can be
+//  repeated exactly and any difference in when the program
+//  asks for input is caught. The log is a text file with
+//  one read per line:
+//
+//  # bf-cli input recording
+//  12 104       instruction count, byte read
+//  40 eof       end of input
+
+pub struct InputEvent {
+    pub inst_count : u64,
+
+    // None at the end of input
+    pub value : Option<u8>
+}
+
+pub enum InputLog {
+    Record(Box<dyn Write>),
+    Replay(Replay)
+}
+
+pub struct Replay {
+    events : Vec<InputEvent>,
+    next : usize
+}
+
+pub const RECORD_HEADER : &str = "# bf-cli input recording";
+
+impl InputEvent {
+    pub fn format(&self) -> String {
+        match self.value {
+            Some(value) => format!("{} {}", self.inst_count, value),
+            None => format!("{} eof", self.inst_count)
+        }
+    }
+}
+
+impl Replay {
+    pub fn parse(text : &str) -> Result<Replay, String> {
+        let mut events = vec![];
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = || format!("line {}: Expected '<instruction count> <byte>' or '<instruction count> eof', found '{}'", line_number + 1, line);
+            let words : Vec<&str> = line.split_whitespace().collect();
+            if words.len() != 2 {
+                return Err(error());
+            }
+
+            let inst_count = words[0].parse().map_err(|_| error())?;
+            let value = match words[1] {
+                "eof" => None,
+                value => Some(value.parse().map_err(|_| error())?)
+            };
+            events.push(InputEvent { inst_count, value });
+        }
+
+        Ok(Replay { events, next : 0 })
+    }
+
+    // Gives the next recorded byte, failing if the program
+    //  has asked for it at a different point to the recording.
+    pub(crate) fn next(&mut self, inst_count : u64) -> Result<Option<u8>, String> {
+        let event = match self.events.get(self.next) {
+            Some(event) => event,
+            None => return Err(format!("Replay diverged: input requested after {} instructions but the recording has no more input", inst_count))
+        };
+
+        if event.inst_count != inst_count {
+            return Err(format!("Replay diverged: input {} was recorded after {} instructions but requested after {}",
+                self.next + 1, event.inst_count, inst_count));
+        }
+
+        self.next += 1;
+        Ok(event.value)
+    }
+
+    // Recorded reads the program didn't ask for
+    pub fn remaining(&self) -> usize {
+        self.events.len() - self.next
+    }
+}
@@ -0,0 +1,104 @@
+use bf_vm::BFVM;
+
+// Starting tape contents, loaded into a VM before it runs.
+//
+// The text format is a list of cell values (-128 to 255)
+//  separated by spaces, commas or new lines, which fill
+//  consecutive cells from the starting cell:
+//
+//  # Comments run to the end of the line
+//  72 101 108 108 111
+//  30010: 1 2 @3 4     'n:' moves to cell n, '@' marks
+//                       the cell the data pointer starts on
+pub struct TapeImage {
+    // Runs of cells, each with the cell it starts at
+    pub blocks : Vec<(usize, Vec<i8>)>,
+
+    // Where the data pointer starts, None to leave it
+    pub data_ptr : Option<usize>
+}
+
+impl TapeImage {
+    // Parses the text format, values without a 'n:' before
+    //  them start at the start cell.
+    pub fn from_text(text : &str, start : usize) -> Result<TapeImage, String> {
+        let mut image = TapeImage { blocks : vec![(start, vec![])], data_ptr : None };
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+
+            for word in line.split(|c : char| c.is_whitespace() || c == ',').filter(|word| !word.is_empty()) {
+                let error = |message : &str| format!("line {}: {} '{}'", line_number + 1, message, word);
+
+                if let Some(cell) = word.strip_suffix(':') {
+                    let cell = cell.parse().map_err(|_| error("Expected a cell number, found"))?;
+                    image.blocks.push((cell, vec![]));
+                    continue;
+                }
+
+                let (marked, value) = match word.strip_prefix('@') {
+                    Some(value) => (true, value),
+                    None => (false, word)
+                };
+
+                let value = match value.parse::<i16>() {
+                    Ok(value) if (-128..=255).contains(&value) => value as u8 as i8,
+                    _ => return Err(error("Expected a cell value from -128 to 255, found"))
+                };
+
+                let block = image.blocks.last_mut().unwrap();
+                if marked {
+                    if image.data_ptr.is_some() {
+                        return Err(error("The data pointer has already been marked, found"));
+                    }
+                    match block.0.checked_add(block.1.len()) {
+                        Some(cell) => image.data_ptr = Some(cell),
+                        None => return Err(error("The marked cell is past the last possible cell, found"))
+                    }
+                }
+                block.1.push(value);
+            }
+        }
+
+        Ok(image)
+    }
+
+    // Raw bytes, one per cell, filling cells from start.
+    pub fn from_bytes(bytes : &[u8], start : usize) -> TapeImage {
+        TapeImage {
+            blocks : vec![(start, bytes.iter().map(|byte| *byte as i8).collect())],
+            data_ptr : None
+        }
+    }
+
+    // Copies the cells into the VM's memory and moves its
+    //  data pointer. Fails if anything is outside memory.
+    pub fn load(&self, vm : &mut BFVM) -> Result<(), String> {
+        let size = vm.memory().len();
+
+        for &(start, ref cells) in &self.blocks {
+            if cells.is_empty() {
+                continue;
+            }
+            // Cell numbers in the file can be anything, so the
+            //  end is checked for overflow as well as the size
+            let end = match start.checked_add(cells.len()) {
+                Some(end) if end <= size => end,
+                _ => return Err(match start.checked_add(cells.len() - 1) {
+                    Some(last) => format!("Cells {} to {} are outside the tape of {} cells", start, last, size),
+                    None => format!("Cells from {} are outside the tape of {} cells", start, size)
+                })
+            };
+            vm.memory_mut()[start..end].copy_from_slice(cells);
+        }
+
+        if let Some(data_ptr) = self.data_ptr {
+            if data_ptr >= size {
+                return Err(format!("Data pointer {} is outside the tape of {} cells", data_ptr, size));
+            }
+            vm.set_data_ptr(data_ptr);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::io;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Slows BFVM::run down so animations can be watched.
+//
+// The speed is shared (through an Arc) so it can be changed
+//  from another thread, e.g. one reading keys, while the
+//  program is running.
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SpeedUnit {
+    // Rate is in tokens per second. Note repeated commands
+    //  such as +++ are a single token.
+    Instructions,
+    // Rate is in '.' outputs per second
+    Output
+}
+
+pub struct Speed {
+    pub unit : SpeedUnit,
+    rate : AtomicU64,
+    paused : AtomicBool
+}
+
+impl Speed {
+    pub fn new(rate : u64, unit : SpeedUnit) -> Arc<Speed> {
+        Arc::new(Speed {
+            unit,
+            rate : AtomicU64::new(rate.max(1)),
+            paused : AtomicBool::new(false)
+        })
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    pub fn set_rate(&self, rate : u64) {
+        self.rate.store(rate.max(1), Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused : bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+}
+
+// Timing for a single run.
+pub(crate) struct Throttle {
+    speed : Arc<Speed>,
+
+    // Stops pauses and sleeps early once set
+    interrupt : Option<&'static AtomicBool>,
+
+    // Rate the count is measured against, timing starts
+    //  again whenever it changes.
+    rate : u64,
+    count : u64,
+    start : Instant
+}
+
+// How far ahead of schedule the program can get before
+//  sleeping, so fast rates don't sleep on every instruction.
+const SLEEP_AHEAD : Duration = Duration::from_millis(2);
+
+// How often to check whether a paused program has resumed
+const PAUSE_POLL : Duration = Duration::from_millis(50);
+
+impl Throttle {
+    pub(crate) fn new(speed : Arc<Speed>, interrupt : Option<&'static AtomicBool>) -> Throttle {
+        Throttle {
+            rate : speed.rate(),
+            speed,
+            interrupt,
+            count : 0,
+            start : Instant::now()
+        }
+    }
+
+    pub(crate) fn unit(&self) -> SpeedUnit {
+        self.speed.unit
+    }
+
+    // Counts one unit, sleeping if the program is ahead of
+    //  the rate. Output is flushed before sleeping so it's
+    //  seen straight away.
+    pub(crate) fn tick(&mut self, writer : &mut dyn Write) -> io::Result<()> {
+        if self.speed.is_paused() {
+            writer.flush()?;
+            while self.speed.is_paused() && !self.interrupted() {
+                thread::sleep(PAUSE_POLL);
+            }
+            self.restart();
+        }
+
+        if self.speed.rate() != self.rate {
+            self.restart();
+        }
+
+        self.count += 1;
+        let due = Duration::from_secs_f64(self.count as f64 / self.rate as f64);
+        let elapsed = self.start.elapsed();
+        if due > elapsed + SLEEP_AHEAD {
+            writer.flush()?;
+            // Sleep in steps so Ctrl-C isn't kept waiting at
+            //  very slow rates
+            let mut remaining = due - elapsed;
+            while remaining > Duration::from_millis(0) && !self.interrupted() {
+                let step = remaining.min(PAUSE_POLL);
+                thread::sleep(step);
+                remaining -= step;
+            }
+        }
+        Ok(())
+    }
+
+    fn interrupted(&self) -> bool {
+        match self.interrupt {
+            Some(flag) => flag.load(Ordering::Relaxed),
+            None => false
+        }
+    }
+
+    // Starts timing again from now.
+    pub(crate) fn restart(&mut self) {
+        self.rate = self.speed.rate();
+        self.count = 0;
+        self.start = Instant::now();
+    }
+}
@@ -1,9 +1,16 @@
 use traits::*;
 use bf_lexer::*;
+use bf_bytecode::Bytecode;
+use bf_throttle::*;
+use bf_record::*;
+use bf_output::format_tape_window;
 extern crate std;
 use std::io;
 use std::io::Write;
 use std::io::Read;
+use std::str;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 // Struct for our Virtual Machine that interprets
 //  the Brainfuck tokens
@@ -11,41 +18,121 @@ pub struct BFVM {
 
     // Memory storage for the BF programs to use
     //  Note: 60000 bytes is double standard BF size
-    mem : [i8; 60000],
+    pub(crate) mem : [i8; 60000],
 
     // Current location BF program is looking at
     //  in memory.
-    data_ptr : usize,
+    pub(crate) data_ptr : usize,
 
     // Current location the VM is running instructions
     //  from (using it's token list, not 'mem' memory)
     inst_ptr : usize,
 
+    // Number of instructions run so far, only counted by
+    //  the interpreter (run and step).
+    inst_count : u64,
+
     // Settings that can be changed via input args
-    settings : VMSettings
+    settings : VMSettings,
+
+    // Where ',' reads from and '.' writes to,
+    //  stdin and stdout unless changed.
+    reader : Box<dyn Read>,
+    writer : Box<dyn Write>,
+
+    // Where '#' debug tokens write to, stderr by default
+    debug_writer : Box<dyn Write>,
+
+    // Bytes of a UTF-8 character that hasn't been
+    //  completely output yet.
+    utf8_pending : Vec<u8>,
+
+    // Limits how fast run goes, if a speed was set
+    throttle : Option<Throttle>,
+
+    // Input being recorded or replayed
+    input_log : Option<InputLog>,
+
+    // Set when an instruction fails, the engines check this
+    //  after input and output and turn it into a
+    //  VMResult::Error.
+    pub(crate) error : Option<String>,
+
+    // Token the error happened on, for engines that don't
+    //  keep inst_ptr up to date but know it when they fail.
+    error_inst_ptr : Option<usize>,
+
+    // Set when output fails because whatever was reading it
+    //  has gone, e.g. the end of a pipe was closed.
+    output_closed : bool
 }
 
 // Extra settings for the VM
 //  Should these just in the BFVM struct?
 pub struct VMSettings {
 
-    // Controls if we show the prompt or not.
-    // Input from a file should naturally work by
-    //  controlling stdin via a pipe could use
-    //  isatty to determine this automatically
-    //  later on. 
+    // Controls if we show a prompt before every ','.
+    // This is off by default, the CLI reads whole
+    //  edited lines instead when stdin is a terminal.
     pub prompt_for_input : bool,
 
+    // How '.' writes out the current cell
+    pub output_mode : OutputMode,
+
+    // Slows down run, None runs as fast as possible
+    pub speed : Option<Arc<Speed>>,
+
+    // When this is set the program stops, returning
+    //  VMResult::Interrupted. Used for SIGINT handlers.
+    pub interrupt : Option<&'static AtomicBool>
+
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum OutputMode {
+    // Each cell is written as a byte
+    Raw,
+    // Each cell is written as a decimal number (0 - 255)
+    //  followed by a space
+    Decimal,
+    // Each cell is written as two hex digits followed
+    //  by a space
+    Hex,
+    // Cells are decoded as UTF-8, so characters split over
+    //  several cells are output whole. Invalid sequences
+    //  are replaced with U+FFFD.
+    Utf8
+}
+
+impl OutputMode {
+    pub fn from_name(name : &str) -> Option<OutputMode> {
+        match name {
+            "raw" => Some(OutputMode::Raw),
+            "decimal" | "dec" => Some(OutputMode::Decimal),
+            "hex" => Some(OutputMode::Hex),
+            "utf8" | "utf-8" => Some(OutputMode::Utf8),
+            _ => None
+        }
+    }
 }
 
 impl VMSettings { 
     pub fn new() -> VMSettings {
         VMSettings {
             prompt_for_input : false,
+            output_mode : OutputMode::Raw,
+            speed : None,
+            interrupt : None
         }
     }
 }
 
+impl Default for VMSettings {
+    fn default() -> VMSettings {
+        VMSettings::new()
+    }
+}
+
 impl VM<BFToken> for BFVM {
 
     // This will loop through the tokens until
@@ -55,18 +142,31 @@ impl VM<BFToken> for BFVM {
 
         //println!("Tokens: {:?}", data);
 
-        let mut reader = io::stdin();
-        let mut writer = io::stdout();
+        // Always start from the first token, memory and the
+        //  data pointer are kept from any previous run.
+        self.inst_ptr = 0;
+
+        if let Some(ref mut throttle) = self.throttle {
+            throttle.restart();
+        }
 
         while self.inst_ptr < data.len() {
-            let result = self.step(&data, &mut reader, &mut writer);
+            if self.throttle.is_some() {
+                self.throttle(&data[self.inst_ptr]);
+            }
+
+            if self.interrupted() {
+                return self.finish(VMResult::Interrupted);
+            }
+
+            let result = self.step(&data);
             match result {
                 VMResult::Success => continue,
-                _ => return result,
+                _ => return self.finish(result),
             }
         }
 
-        VMResult::Success
+        self.finish(VMResult::Success)
     }
 }
 
@@ -84,13 +184,435 @@ impl BFVM {
 
             // Instruction pointer also needs to be 0
             inst_ptr : 0,
+            inst_count : 0,
+
+            reader : Box::new(io::stdin()),
+            writer : Box::new(io::stdout()),
+            debug_writer : Box::new(io::stderr()),
+
+            utf8_pending : vec![],
+
+            throttle : settings.speed.clone().map(|speed| Throttle::new(speed, settings.interrupt)),
+
+            input_log : None,
+            error : None,
+            error_inst_ptr : None,
+            output_closed : false,
+
+            settings
+        }
+    }
+
+    // Reads program input from somewhere other than stdin.
+    pub fn set_input(&mut self, reader : Box<dyn Read>) {
+        self.reader = reader;
+    }
+
+    // Sends program output somewhere other than stdout.
+    pub fn set_output(&mut self, writer : Box<dyn Write>) {
+        self.writer = writer;
+    }
+
+    // Sends the output of '#' debug tokens somewhere other
+    //  than stderr.
+    pub fn set_debug_output(&mut self, writer : Box<dyn Write>) {
+        self.debug_writer = writer;
+    }
+
+    // Records input to, or replays input from, a log.
+    pub fn set_input_log(&mut self, log : InputLog) {
+        self.input_log = Some(log);
+    }
+
+    pub fn input_log(&self) -> Option<&InputLog> {
+        self.input_log.as_ref()
+    }
+
+    // Flushes any output that's been buffered.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let result = self.writer.flush();
+        if let Err(ref error) = result {
+            self.output_closed |= error.kind() == io::ErrorKind::BrokenPipe;
+        }
+        result
+    }
+
+    // True once output has failed because nothing is reading
+    //  it any more, such as a closed pipe.
+    pub fn output_closed(&self) -> bool {
+        self.output_closed
+    }
+
+    // Called once the program has stopped, writes out any
+    //  incomplete UTF-8 character and flushes. Gives back the
+    //  result the program stopped with, or an error if the
+    //  output couldn't be written.
+    pub(crate) fn finish(&mut self, result : VMResult) -> VMResult {
+        if !self.utf8_pending.is_empty() {
+            self.utf8_pending.clear();
+            if let Err(error) = self.writer.write_all("\u{FFFD}".as_bytes()) {
+                self.output_failed(error);
+            }
+        }
+        if let Err(error) = self.flush() {
+            self.output_failed(error);
+        }
+
+        if let Some(InputLog::Record(ref mut writer)) = self.input_log {
+            if let Err(error) = writer.flush() {
+                self.error.get_or_insert(format!("Unable to write input recording: {}", error));
+            }
+        }
+
+        // An earlier error is the one worth reporting
+        match (result, self.error.take()) {
+            (VMResult::Error { message, inst_ptr }, _) => VMResult::Error { message, inst_ptr },
+            (_, Some(message)) => VMResult::Error { message, inst_ptr : self.error_inst_ptr.take() },
+            (result, None) => result
+        }
+    }
+
+    // Records a failed write, for the engines to pick up.
+    fn output_failed(&mut self, error : io::Error) {
+        self.output_closed |= error.kind() == io::ErrorKind::BrokenPipe;
+        self.error.get_or_insert(format!("Unable to write output: {}", error));
+    }
+
+    // Current position in the token list
+    pub fn inst_ptr(&self) -> usize {
+        self.inst_ptr
+    }
+
+    // Number of instructions run by the interpreter
+    pub fn inst_count(&self) -> u64 {
+        self.inst_count
+    }
+
+    // Current position on the tape
+    pub fn data_ptr(&self) -> usize {
+        self.data_ptr
+    }
+
+    pub fn memory(&self) -> &[i8] {
+        &self.mem
+    }
 
-            settings : settings
+    pub fn memory_mut(&mut self) -> &mut [i8] {
+        &mut self.mem
+    }
+
+    // Moves the data pointer, panics if it's outside memory.
+    pub fn set_data_ptr(&mut self, data_ptr : usize) {
+        assert!(data_ptr < self.mem.len(), "Data pointer {} is outside memory", data_ptr);
+        self.data_ptr = data_ptr;
+    }
+
+    // Clears memory and puts the pointers back to the start.
+    pub fn reset(&mut self) {
+        self.mem = [0; 60000];
+        self.data_ptr = 30000;
+        self.inst_ptr = 0;
+    }
+
+    // Speed run is limited to, which can be changed while
+    //  it runs.
+    pub fn speed(&self) -> Option<&Arc<Speed>> {
+        self.settings.speed.as_ref()
+    }
+
+    // Runs compiled bytecode, a faster alternative to run.
+    //  Like the closure engine this uses the VM's memory and
+    //  data pointer but not its instruction pointer, errors
+    //  are mapped back to the token that failed.
+    pub fn run_bytecode(&mut self, bytecode : &Bytecode) -> VMResult {
+        use bf_bytecode::*;
+
+        let code = &bytecode.code[..];
+        let mut pc : usize = 0;
+
+        // No bounds check on pc is needed as the bytecode
+        //  always ends with HALT.
+        loop {
+            let instruction = code[pc];
+            match instruction & 0xFF {
+                MOVE => {
+                    let target = self.data_ptr as isize + operand(instruction);
+                    if let Some(message) = self.outside_tape(target, true) {
+                        self.error = Some(message);
+                        break;
+                    }
+                    self.data_ptr = target as usize;
+                },
+                ADD => self.mem[self.data_ptr] = self.mem[self.data_ptr].wrapping_add(operand(instruction) as i8),
+                OUTPUT => {
+                    self.output();
+                    if self.error.is_some() {
+                        break;
+                    }
+                },
+                INPUT => {
+                    if !self.input() {
+                        return self.finish(VMResult::Interrupted);
+                    }
+                    if self.error.is_some() {
+                        break;
+                    }
+                },
+                JUMP_ZERO => {
+                    if self.mem[self.data_ptr] == 0 {
+                        pc = (pc as isize + operand(instruction)) as usize;
+                    }
+                },
+                JUMP_NON_ZERO => {
+                    if self.mem[self.data_ptr] != 0 {
+                        // Only loops can run forever, so only
+                        //  check for interrupts here.
+                        if self.interrupted() {
+                            return self.finish(VMResult::Interrupted);
+                        }
+                        pc = (pc as isize + operand(instruction)) as usize;
+                    }
+                },
+                SET_BLOCK => {
+                    let count = (instruction >> 16) as usize;
+                    let value = (instruction >> 8) as u8 as i8;
+                    if let Some(message) = self.outside_tape((self.data_ptr + count) as isize - 1, true) {
+                        self.error = Some(message);
+                        break;
+                    }
+                    for cell in &mut self.mem[self.data_ptr..self.data_ptr + count] {
+                        *cell = value;
+                    }
+                    self.data_ptr += count - 1;
+                },
+                ADD_TO => {
+                    let target = self.data_ptr as isize + operand(instruction);
+                    if let Some(message) = self.outside_tape(target, false) {
+                        self.error = Some(message);
+                        break;
+                    }
+                    let target = target as usize;
+                    self.mem[target] = self.mem[target].wrapping_add(self.mem[self.data_ptr]);
+                    self.mem[self.data_ptr] = 0;
+                },
+                DEBUG => {
+                    self.debug(None);
+                    if self.error.is_some() {
+                        break;
+                    }
+                },
+                _ => break
+            }
+            pc += 1;
+        }
+
+        // Any error is picked up by finish, pc is still on the
+        //  instruction that failed
+        if self.error.is_some() {
+            self.error_inst_ptr = Some(bytecode.tokens[pc]);
+        }
+        self.finish(VMResult::Success)
+    }
+
+    // True if the program should stop, because of an error
+    //  or an interrupt.
+    pub(crate) fn halted(&self) -> bool {
+        self.error.is_some() || self.interrupted()
+    }
+
+    // True once the interrupt flag has been set.
+    pub(crate) fn interrupted(&self) -> bool {
+        match self.settings.interrupt {
+            Some(flag) => flag.load(Ordering::Relaxed),
+            None => false
+        }
+    }
+
+    // Waits if the program is running faster than its speed.
+    fn throttle(&mut self, token : &BFToken) {
+        if let Some(ref mut throttle) = self.throttle {
+            let counted = match throttle.unit() {
+                SpeedUnit::Instructions => true,
+                SpeedUnit::Output => token.token_type == BFTokenType::Output
+            };
+            if counted {
+                if let Err(error) = throttle.tick(&mut self.writer) {
+                    self.output_failed(error);
+                }
+            }
+        }
+    }
+
+    // Write current cell to the output, as a byte unless
+    //  another output mode has been chosen.
+    pub(crate) fn output(&mut self) {
+        let byte = self.mem[self.data_ptr] as u8;
+        let result = match self.settings.output_mode {
+            OutputMode::Raw => self.writer.write_all(&[byte]),
+            OutputMode::Decimal => write!(self.writer, "{} ", byte),
+            OutputMode::Hex => write!(self.writer, "{:02x} ", byte),
+            OutputMode::Utf8 => {
+                self.utf8_pending.push(byte);
+                self.output_utf8()
+            }
+        };
+
+        if let Err(error) = result {
+            self.output_failed(error);
+        }
+    }
+
+    // Writes out as much of the pending UTF-8 as possible,
+    //  keeping back a character that isn't complete yet.
+    fn output_utf8(&mut self) -> io::Result<()> {
+        loop {
+            let (valid, invalid) = match str::from_utf8(&self.utf8_pending) {
+                Ok(text) => (text.len(), None),
+                Err(error) => (error.valid_up_to(), error.error_len())
+            };
+
+            self.writer.write_all(&self.utf8_pending[..valid])?;
+
+            match invalid {
+                // Complete, or the last character needs more bytes
+                None => {
+                    self.utf8_pending.drain(..valid);
+                    return Ok(());
+                },
+                Some(length) => {
+                    self.writer.write_all("\u{FFFD}".as_bytes())?;
+                    self.utf8_pending.drain(..valid + length);
+                }
+            }
+        }
+    }
+
+    // Shows the cells around the data pointer for a '#'
+    //  token. Program output is flushed first so the two
+    //  appear in order, the instruction count is only known
+    //  by the interpreter.
+    pub(crate) fn debug(&mut self, inst_count : Option<u64>) {
+        if let Err(error) = self.flush() {
+            self.output_failed(error);
+            return;
+        }
+
+        let heading = match inst_count {
+            Some(count) => format!("# data pointer {}, instruction count {}", self.data_ptr, count),
+            None => format!("# data pointer {}", self.data_ptr)
+        };
+        let window = format_tape_window(&self.mem, self.data_ptr, 4);
+        if let Err(error) = writeln!(self.debug_writer, "{}\n{}", heading, window).and_then(|_| self.debug_writer.flush()) {
+            self.error.get_or_insert(format!("Unable to write debug output: {}", error));
+        }
+    }
+
+    // Read one byte from the input as a signed byte and store
+    //  it in the current cell. Returns false if Ctrl-C stopped
+    //  the read, leaving the cell alone.
+    pub(crate) fn input(&mut self) -> bool {
+        // Replayed input doesn't touch the reader at all
+        if let Some(InputLog::Replay(ref mut replay)) = self.input_log {
+            match replay.next(self.inst_count) {
+                Ok(value) => self.mem[self.data_ptr] = value.map_or(0, |value| value as i8),
+                Err(message) => self.error = Some(message)
+            }
+            return true;
+        }
+
+        // The prompt goes to stderr so it stays out of the
+        //  program's output, which is flushed first so the
+        //  prompt comes after it
+        if self.settings.prompt_for_input {
+            if let Err(error) = self.flush() {
+                self.output_failed(error);
+                return true;
+            }
+            let _ = io::stderr().write_all(b"\n> ");
+        }
+        let mut buffer = [0u8; 1];
+        let read = loop {
+            match self.reader.read(&mut buffer[..]) {
+                Ok(read) => break read,
+                // Ctrl-C while waiting for input, anything else
+                //  that interrupts the read is retried
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {
+                    if self.interrupted() {
+                        return false;
+                    }
+                },
+                Err(error) => {
+                    self.error = Some(format!("Unable to read input: {}", error));
+                    return true;
+                }
+            }
+        };
+        // Nothing read means end of input, which sets the cell to 0
+        self.mem[self.data_ptr] = if read == 0 { 0 } else { buffer[0] as i8 };
+
+        if let Some(InputLog::Record(ref mut writer)) = self.input_log {
+            let event = InputEvent { inst_count : self.inst_count, value : if read == 0 { None } else { Some(buffer[0]) } };
+            if let Err(error) = writeln!(writer, "{}", event.format()) {
+                self.error = Some(format!("Unable to write input recording: {}", error));
+            }
+        }
+        true
+    }
+
+    // Checks a token won't move the data pointer, or reach
+    //  a cell, outside the tape.
+    fn check_bounds(&self, token_type : BFTokenType) -> Option<String> {
+        use bf_lexer::BFTokenType::*;
+
+        let ptr = self.data_ptr as isize;
+        let (cell, moves) = match token_type {
+            IncrementPtr(x) => (ptr + x as isize, true),
+            DecrementPtr(x) => (ptr - x as isize, true),
+            SetBlock(x, _) => (ptr + x as isize - 1, true),
+            AddCurrentUp(x) => (ptr + x as isize, false),
+            AddCurrentDown(x) => (ptr - x as isize, false),
+            _ => return None
+        };
+
+        self.outside_tape(cell, moves)
+    }
+
+    // Describes the problem if a cell isn't on the tape,
+    //  moves is true if the data pointer is going there.
+    fn outside_tape(&self, cell : isize, moves : bool) -> Option<String> {
+        if cell >= 0 && cell < self.mem.len() as isize {
+            None
+        } else if moves {
+            Some(format!("Data pointer moved to cell {}, outside the tape (0 to {})", cell, self.mem.len() - 1))
+        } else {
+            Some(format!("Cell {} is outside the tape (0 to {})", cell, self.mem.len() - 1))
+        }
+    }
+
+    // Used by the closure engine before running a token,
+    //  records an error against the token if it would leave
+    //  the tape.
+    pub(crate) fn in_bounds(&mut self, token_type : BFTokenType, inst_ptr : usize) -> bool {
+        match self.check_bounds(token_type) {
+            Some(message) => {
+                self.error = Some(message);
+                self.error_at(inst_ptr);
+                false
+            },
+            None => true
+        }
+    }
+
+    // Records which token an error set by input or output
+    //  came from, for engines that don't track inst_ptr.
+    pub(crate) fn error_at(&mut self, inst_ptr : usize) {
+        if self.error.is_some() && self.error_inst_ptr.is_none() {
+            self.error_inst_ptr = Some(inst_ptr);
         }
     }
 
     // Interprets the current token.
-    pub fn step(&mut self, data : &Vec<BFToken>, reader : &mut io::Stdin, writer : &mut io::Stdout) -> VMResult {
+    pub fn step(&mut self, data : &[BFToken]) -> VMResult {
         // Import enum -> Allows for using enum values without
         //  BFTokenType:: prefix
         use bf_lexer::BFTokenType::*;
@@ -98,6 +620,10 @@ impl BFVM {
         // Get the current token
         let token = &data[self.inst_ptr];
 
+        if let Some(message) = self.check_bounds(token.token_type) {
+            return VMResult::Error { message, inst_ptr : Some(self.inst_ptr) };
+        }
+
         match token.token_type {
 
             // >    Increments data pointer
@@ -107,28 +633,18 @@ impl BFVM {
             DecrementPtr(x) => self.data_ptr-=x,
 
             // +    Wrapping adds 1 to cell that data pointer is pointing to
-            IncrementData(x) => self.mem[self.data_ptr] = self.mem[self.data_ptr].wrapping_add((x % 255) as i8),
+            IncrementData(x) => self.mem[self.data_ptr] = self.mem[self.data_ptr].wrapping_add(x as i8),
 
             // -    Wrapping subtracts 1 from cell
-            DecrementData(x) => self.mem[self.data_ptr] = self.mem[self.data_ptr].wrapping_sub((x % 255) as i8),
+            DecrementData(x) => self.mem[self.data_ptr] = self.mem[self.data_ptr].wrapping_sub(x as i8),
 
             // .    Prints the current cell as a character to stdout (65 - A)
-            Output => {
-                // Write current cell to stdout as a byte
-                let data = &[self.mem[self.data_ptr] as u8];
-                writer.write(data).expect("Unable to write to STDOUT");
-            },
+            Output => self.output(),
 
             // ,    Reads input from stdin and puts it into current cell
-            Input => {
-                if self.settings.prompt_for_input {
-                    print!("\n> ");
-                    io::stdout().flush().ok().expect("Could not flush stdout");
-                }
-                // Read one byte from stdin as a signed byte and store it
-                let mut buffer = [0u8; 1];
-                reader.read(&mut buffer[..]).expect("Unable to read from STDIN");
-                self.mem[self.data_ptr] = buffer[0] as i8;
+            // Stopped while waiting for input, stay on the ','
+            Input => if !self.input() {
+                return VMResult::Interrupted;
             },
 
             // [     If current data cell is 0 skip to matching ]
@@ -165,10 +681,19 @@ impl BFVM {
             AddCurrentDown(x) => {
                 self.mem[self.data_ptr - x] = self.mem[self.data_ptr - x].wrapping_add(self.mem[self.data_ptr]);
                 self.mem[self.data_ptr] = 0;
-            }
+            },
+
+            // #     Prints the cells around the data pointer
+            Debug => self.debug(Some(self.inst_count))
         }
         
+        // Input and output report failures through error
+        if let Some(message) = self.error.take() {
+            return VMResult::Error { message, inst_ptr : Some(self.inst_ptr) };
+        }
+
         self.inst_ptr+=1;
+        self.inst_count+=1;
 
         VMResult::Success
     }
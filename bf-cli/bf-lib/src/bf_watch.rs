@@ -0,0 +1,96 @@
+use bf_lexer::BFTokenType;
+use bf_vm::BFVM;
+
+use std::fmt;
+
+// Watchpoints for stepping through a program.
+//
+// Optimised tokens can write several cells and move the data
+//  pointer more than one cell in a single BFVM::step, so the
+//  cells a token writes are worked out before it runs and
+//  checked against the tape once it has.
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Watch {
+    // Any write to the cell, even one that leaves it the same
+    Write(usize),
+
+    // The cell is written and changes to the value
+    Value(usize, i8),
+
+    // The data pointer moves into or through the cells from
+    //  the first to the second (inclusive)
+    Pointer(usize, usize)
+}
+
+// The tape before a step, as far as watches need it.
+pub struct StepAccess {
+    // Cells the token writes, with their values beforehand
+    written : Vec<(usize, i8)>,
+    data_ptr : usize
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Watch::Write(cell) => write!(f, "cell {} written", cell),
+            Watch::Value(cell, value) => write!(f, "cell {} becomes {}", cell, value),
+            Watch::Pointer(from, to) if from == to => write!(f, "data pointer enters cell {}", from),
+            Watch::Pointer(from, to) => write!(f, "data pointer enters cells {} to {}", from, to)
+        }
+    }
+}
+
+// Cells a token will write when the data pointer is at
+//  data_ptr. Cells past the end of the tape are left out,
+//  the step itself fails on those.
+pub fn cells_written(token_type : BFTokenType, data_ptr : usize, size : usize) -> Vec<usize> {
+    use bf_lexer::BFTokenType::*;
+
+    let cells = match token_type {
+        IncrementData(_) | DecrementData(_) | Input => vec![data_ptr],
+        SetBlock(x, _) => (data_ptr..data_ptr + x).collect(),
+        AddCurrentUp(x) => vec![data_ptr, data_ptr + x],
+        AddCurrentDown(x) if x <= data_ptr => vec![data_ptr - x, data_ptr],
+        AddCurrentDown(_) => vec![data_ptr],
+        IncrementPtr(_) | DecrementPtr(_) | Output | LoopStart(_) | LoopEnd(_) | Debug => vec![]
+    };
+
+    cells.into_iter().filter(|cell| *cell < size).collect()
+}
+
+impl StepAccess {
+    // Call before stepping over the token.
+    pub fn before(vm : &BFVM, token_type : BFTokenType) -> StepAccess {
+        let mem = vm.memory();
+        StepAccess {
+            written : cells_written(token_type, vm.data_ptr(), mem.len()).into_iter().map(|cell| (cell, mem[cell])).collect(),
+            data_ptr : vm.data_ptr()
+        }
+    }
+
+    // Call after the step, describes what set the watch off,
+    //  if it was.
+    pub fn triggered(&self, watch : Watch, vm : &BFVM) -> Option<String> {
+        match watch {
+            Watch::Write(cell) => self.written.iter().find(|&&(written, _)| written == cell)
+                .map(|&(_, old)| format!("cell {} written ({} -> {})", cell, old, vm.memory()[cell])),
+
+            Watch::Value(cell, value) => self.written.iter()
+                .find(|&&(written, old)| written == cell && old != value && vm.memory()[cell] == value)
+                .map(|&(_, old)| format!("cell {} became {} (was {})", cell, value, old)),
+
+            Watch::Pointer(from, to) => {
+                // Everything between the old and new pointer was
+                //  passed over, as it would be with single moves
+                let (low, high) = if vm.data_ptr() < self.data_ptr { (vm.data_ptr(), self.data_ptr) } else { (self.data_ptr, vm.data_ptr()) };
+                let was_inside = self.data_ptr >= from && self.data_ptr <= to;
+                if !was_inside && low <= to && high >= from {
+                    Some(format!("{} (now at {})", watch, vm.data_ptr()))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
@@ -6,9 +6,20 @@ pub mod traits;
 pub mod bf_lexer;
 pub mod bf_vm;
 pub mod bf_output;
+pub mod bf_throttle;
+pub mod bf_tape;
+pub mod bf_record;
+pub mod bf_watch;
 
-// Tests - Currently none
-//  TODO: Add tests
+// Alternative execution engines
+pub mod bf_closure;
+pub mod bf_bytecode;
+
+// Ahead-of-time compilers
+pub mod bf_compiler;
+
+// Tests
+#[cfg(test)]
 mod tests;
 
 
@@ -22,6 +22,24 @@ pub trait VM<T> {
 #[derive(Debug, PartialEq)]
 pub enum VMResult {
     Success,
-    Error { message: String }
+    // inst_ptr is the token that failed, when it's known.
+    //  It isn't for failures outside of any token, such as
+    //  flushing output once the program has finished.
+    Error { message: String, inst_ptr: Option<usize> },
+    // Stopped early by VMSettings.interrupt
+    Interrupted
 }
 
+
+// Compiler
+// ==================================================================
+
+pub trait Compiler<T> {
+    fn compile(&self, data : Vec<T>) -> CompileResult;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CompileResult {
+    Success(Vec<u8>),
+    Error { message: String }
+}
@@ -0,0 +1,278 @@
+// Command line parsing
+//
+// Each subcommand describes its options in a table, which
+//  is used both to parse the arguments and to generate the
+//  --help text, so the two can't get out of step.
+
+use std::collections::HashMap;
+
+// A single option a subcommand accepts.
+pub struct Opt {
+    pub short : Option<&'static str>,
+    pub long : &'static str,
+
+    // Name of the value the option takes, None for flags
+    pub value : Option<&'static str>,
+
+    pub help : &'static str
+}
+
+// A subcommand and the options it accepts.
+pub struct Command {
+    pub name : &'static str,
+    pub about : &'static str,
+
+    // Name of the positional arguments, None if it takes none
+    pub positional : Option<&'static str>,
+
+    pub options : &'static [Opt]
+}
+
+// Parsed arguments for a subcommand.
+#[derive(Debug)]
+pub struct Args {
+    // Values keyed by long option name, flags have an empty value
+    values : HashMap<&'static str, String>,
+    pub positional : Vec<String>
+}
+
+impl Args {
+    pub fn flag(&self, long : &str) -> bool {
+        self.values.contains_key(long)
+    }
+
+    pub fn value(&self, long : &str) -> Option<&str> {
+        self.values.get(long).map(|value| value.as_ref())
+    }
+}
+
+impl Command {
+    fn find(&self, argument : &str) -> Option<&'static Opt> {
+        self.options.iter().find(|opt| {
+            argument == opt.long || Some(argument) == opt.short
+        })
+    }
+
+    // Parses the arguments following the subcommand name.
+    pub fn parse(&self, arguments : &[String]) -> Result<Args, String> {
+        let mut args = Args { values : HashMap::new(), positional : vec![] };
+        let mut iter = arguments.iter();
+
+        while let Some(argument) = iter.next() {
+            // A lone '-' is a positional (stdin), '--' ends the options
+            if argument == "--" {
+                args.positional.extend(iter.cloned());
+                break;
+            }
+            if !argument.starts_with('-') || argument == "-" {
+                if self.positional.is_none() {
+                    return Err(format!("'{}' doesn't take any arguments, found '{}'", self.name, argument));
+                }
+                args.positional.push(argument.clone());
+                continue;
+            }
+
+            // Split --key=value
+            let (name, inline) = match argument.find('=') {
+                Some(index) if argument.starts_with("--") => (&argument[..index], Some(&argument[index + 1..])),
+                _ => (&argument[..], None)
+            };
+
+            let opt = match self.find(name) {
+                Some(opt) => opt,
+                None => return Err(format!("Unknown option '{}' for '{}'", name, self.name))
+            };
+
+            if args.values.contains_key(opt.long) {
+                return Err(format!("'{}' was given more than once", opt.long));
+            }
+
+            let value = match (opt.value, inline) {
+                (None, None) => String::new(),
+                (None, Some(_)) => return Err(format!("'{}' doesn't take a value", opt.long)),
+                (Some(_), Some(value)) => String::from(value),
+                (Some(value_name), None) => match iter.next() {
+                    Some(value) => value.clone(),
+                    None => return Err(format!("'{}' needs a value <{}>", opt.long, value_name))
+                }
+            };
+
+            args.values.insert(opt.long, value);
+        }
+
+        Ok(args)
+    }
+
+    // Generates the help text from the option table.
+    pub fn help(&self) -> String {
+        let mut help = format!("{}\n\nUsage:\n    bf-cli {} [options]", self.about, self.name);
+        if let Some(positional) = self.positional {
+            help.push_str(&format!(" {}", positional));
+        }
+        help.push_str("\n\nOptions:\n");
+
+        let mut lines : Vec<(String, &str)> = self.options.iter().map(|opt| {
+            let mut names = match opt.short {
+                Some(short) => format!("{}, {}", short, opt.long),
+                None => format!("    {}", opt.long)
+            };
+            if let Some(value) = opt.value {
+                names.push_str(&format!(" <{}>", value));
+            }
+            (names, opt.help)
+        }).collect();
+        lines.push((String::from("-h, --help"), "Shows this screen."));
+
+        let width = lines.iter().map(|line| line.0.len()).max().unwrap_or(0) + 2;
+        for (names, text) in lines {
+            help.push_str(&format!("    {:width$}{}\n", names, text, width = width));
+        }
+
+        help
+    }
+}
+
+// Expands the escapes \n, \r, \t, \0, \\ and \xHH in text
+//  given on the command line.
+pub fn unescape(text : &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    let mut chars = text.chars();
+
+    while let Some(character) = chars.next() {
+        if character != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(character.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                // from_str_radix also takes a sign, so check the
+                //  digits first
+                let hex : String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) => bytes.push(byte),
+                    _ => return Err(format!("Invalid escape '\\x{}', expected two hex digits", hex))
+                }
+            },
+            Some(other) => return Err(format!("Unknown escape '\\{}'", other)),
+            None => return Err(String::from("Escape '\\' at the end of the input"))
+        }
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TEST : Command = Command {
+        name : "test",
+        about : "Options for testing the parser",
+        positional : Some("<file>..."),
+        options : &[
+            Opt { short : Some("-s"), long : "--str", value : Some("bfstring"), help : "A value." },
+            Opt { short : None, long : "--raw", value : None, help : "A flag." }
+        ]
+    };
+
+    static NO_FILES : Command = Command {
+        name : "none",
+        about : "Takes no positional arguments",
+        positional : None,
+        options : &[]
+    };
+
+    fn parse(command : &Command, arguments : &[&str]) -> Result<Args, String> {
+        let arguments : Vec<String> = arguments.iter().map(|argument| argument.to_string()).collect();
+        command.parse(&arguments)
+    }
+
+    #[test]
+    fn parses_flags_and_values() {
+        let args = parse(&TEST, &["--raw", "-s", "+."]).unwrap();
+        assert!(args.flag("--raw"));
+        assert_eq!(args.value("--str"), Some("+."));
+        assert!(args.positional.is_empty());
+
+        let args = parse(&TEST, &[]).unwrap();
+        assert!(!args.flag("--raw"));
+        assert_eq!(args.value("--str"), None);
+    }
+
+    #[test]
+    fn parses_inline_values() {
+        let args = parse(&TEST, &["--str=a=b"]).unwrap();
+        assert_eq!(args.value("--str"), Some("a=b"));
+
+        let args = parse(&TEST, &["--str="]).unwrap();
+        assert_eq!(args.value("--str"), Some(""));
+
+        // Only long options can be given a value this way
+        assert_eq!(parse(&TEST, &["-s=+"]).unwrap_err(), "Unknown option '-s=+' for 'test'");
+    }
+
+    #[test]
+    fn collects_positional_files() {
+        let args = parse(&TEST, &["a.bf", "--raw", "-", "b.bf"]).unwrap();
+        assert_eq!(args.positional, vec!["a.bf", "-", "b.bf"]);
+
+        // Anything after -- is positional, even if it looks like an option
+        let args = parse(&TEST, &["--", "--raw", "-s"]).unwrap();
+        assert!(!args.flag("--raw"));
+        assert_eq!(args.positional, vec!["--raw", "-s"]);
+
+        assert_eq!(parse(&NO_FILES, &["a.bf"]).unwrap_err(), "'none' doesn't take any arguments, found 'a.bf'");
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        assert_eq!(parse(&TEST, &["--speed", "10"]).unwrap_err(), "Unknown option '--speed' for 'test'");
+        assert_eq!(parse(&TEST, &["--speed=10"]).unwrap_err(), "Unknown option '--speed' for 'test'");
+        assert_eq!(parse(&TEST, &["-x"]).unwrap_err(), "Unknown option '-x' for 'test'");
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        assert_eq!(parse(&TEST, &["--raw", "--raw"]).unwrap_err(), "'--raw' was given more than once");
+
+        // The short and long names are the same option
+        assert_eq!(parse(&TEST, &["-s", "+", "--str=-"]).unwrap_err(), "'--str' was given more than once");
+    }
+
+    #[test]
+    fn rejects_missing_values() {
+        assert_eq!(parse(&TEST, &["-s"]).unwrap_err(), "'--str' needs a value <bfstring>");
+        assert_eq!(parse(&TEST, &["--raw", "--str"]).unwrap_err(), "'--str' needs a value <bfstring>");
+    }
+
+    #[test]
+    fn unescapes_input() {
+        assert_eq!(unescape("a\\n\\r\\t\\0\\\\b").unwrap(), b"a\n\r\t\0\\b".to_vec());
+        assert_eq!(unescape("\\x41\\xff\\x0a").unwrap(), vec![0x41, 0xFF, 0x0A]);
+        assert_eq!(unescape("é").unwrap(), "é".as_bytes().to_vec());
+        assert_eq!(unescape("").unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn rejects_bad_escapes() {
+        assert_eq!(unescape("\\x4").unwrap_err(), "Invalid escape '\\x4', expected two hex digits");
+        assert_eq!(unescape("\\x").unwrap_err(), "Invalid escape '\\x', expected two hex digits");
+        assert_eq!(unescape("\\xg1").unwrap_err(), "Invalid escape '\\xg1', expected two hex digits");
+        assert_eq!(unescape("\\x+1").unwrap_err(), "Invalid escape '\\x+1', expected two hex digits");
+        assert_eq!(unescape("\\q").unwrap_err(), "Unknown escape '\\q'");
+        assert_eq!(unescape("ab\\").unwrap_err(), "Escape '\\' at the end of the input");
+    }
+
+    #[test]
+    fn rejects_values_for_flags() {
+        assert_eq!(parse(&TEST, &["--raw=yes"]).unwrap_err(), "'--raw' doesn't take a value");
+        assert_eq!(parse(&TEST, &["--raw="]).unwrap_err(), "'--raw' doesn't take a value");
+    }
+}
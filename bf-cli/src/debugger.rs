@@ -0,0 +1,247 @@
+// Interactive debugger
+//
+// Steps through the token list one instruction at a time,
+//  with breakpoints on instruction positions, watchpoints on
+//  cells and the data pointer, and a view of the tape around
+//  the data pointer.
+
+use bf_lib::traits::*;
+use bf_lib::bf_lexer::BFToken;
+use bf_lib::bf_vm::BFVM;
+use bf_lib::bf_output::format_tape_window;
+use bf_lib::bf_watch::{Watch, StepAccess};
+
+use interrupt;
+use report;
+use source::Source;
+
+use std::collections::BTreeSet;
+use std::io;
+use std::io::prelude::*;
+
+const HELP : &str = "
+Commands:
+    s, step [n]         Run the next n instructions (default 1)
+    c, continue         Run until a breakpoint, watchpoint or the end of the program
+    b, break <inst>     Set a breakpoint at an instruction
+    d, delete <inst>    Remove a breakpoint
+    w, watch <cell> [value]
+                        Stop when a cell is written, or when it changes to value
+    wp, watchptr <from> [to]
+                        Stop when the data pointer moves into the cells from..to
+    uw, unwatch <n>     Remove watchpoint n
+    i, info             List breakpoints and watchpoints
+    t, tape [radius]    Show the cells around the data pointer (default 8)
+    l, list [radius]    Show the instructions around the current one (default 4)
+    q, quit             Stop debugging
+    h, help             Shows this screen.
+";
+
+pub struct Debugger {
+    vm : BFVM,
+    tokens : Vec<BFToken>,
+    source : Source,
+    breakpoints : BTreeSet<usize>,
+    watches : Vec<Watch>,
+
+    // Set when the last step set off a watchpoint
+    watch_hit : bool
+}
+
+impl Debugger {
+    pub fn new(vm : BFVM, tokens : Vec<BFToken>, source : Source) -> Debugger {
+        Debugger {
+            vm,
+            tokens,
+            source,
+            breakpoints : BTreeSet::new(),
+            watches : vec![],
+            watch_hit : false
+        }
+    }
+
+    // Runs the command loop until the user quits. Returns
+    //  the result of the last instruction run.
+    pub fn run(&mut self) -> VMResult {
+        let reader = io::stdin();
+        let mut result = VMResult::Success;
+
+        println!("{} instructions loaded. Type 'h' for help.", self.tokens.len());
+        self.show_current();
+
+        loop {
+            print!("(bfdb) ");
+            io::stdout().flush().expect("Could not flush stdout");
+
+            let mut line = String::new();
+            if reader.read_line(&mut line).expect("Unable to read from STDIN") == 0 {
+                return result;
+            }
+            // Ctrl-C at the prompt doesn't stop the next command
+            interrupt::clear();
+
+            let words : Vec<&str> = line.split_whitespace().collect();
+            let number = words.get(1).map(|word| word.parse::<usize>());
+
+            match (words.first().cloned(), number) {
+                (None, _) => continue,
+                (_, Some(Err(_))) => println!("Expected a number, found '{}'", words[1]),
+                (Some("s"), n) | (Some("step"), n) => {
+                    let count = match n { Some(Ok(n)) => n, _ => 1 };
+                    for _ in 0..count {
+                        result = self.step();
+                        if result != VMResult::Success || self.finished() || self.watch_hit {
+                            break;
+                        }
+                    }
+                    result = self.stopped(result);
+                    self.show_current();
+                },
+                (Some("c"), _) | (Some("continue"), _) => {
+                    // Always move off the current instruction first so
+                    //  continuing from a breakpoint doesn't stop straight away
+                    result = self.step();
+                    while result == VMResult::Success && !self.finished() && !self.watch_hit
+                        && !self.breakpoints.contains(&self.vm.inst_ptr()) {
+                        result = self.step();
+                    }
+                    result = self.stopped(result);
+                    self.show_current();
+                },
+                (Some("b"), Some(Ok(inst))) | (Some("break"), Some(Ok(inst))) => {
+                    self.breakpoints.insert(inst);
+                    println!("Breakpoint set at {}", inst);
+                },
+                (Some("d"), Some(Ok(inst))) | (Some("delete"), Some(Ok(inst))) => {
+                    if self.breakpoints.remove(&inst) {
+                        println!("Breakpoint at {} removed", inst);
+                    } else {
+                        println!("No breakpoint at {}", inst);
+                    }
+                },
+                (Some("w"), Some(Ok(cell))) | (Some("watch"), Some(Ok(cell))) => {
+                    let watch = match words.get(2).map(|value| value.parse::<i16>()) {
+                        None => Watch::Write(cell),
+                        Some(Ok(value)) if (-128..=255).contains(&value) => Watch::Value(cell, value as u8 as i8),
+                        Some(_) => {
+                            println!("Expected a cell value from -128 to 255, found '{}'", words[2]);
+                            continue;
+                        }
+                    };
+                    self.add_watch(watch);
+                },
+                (Some("wp"), Some(Ok(from))) | (Some("watchptr"), Some(Ok(from))) => {
+                    match words.get(2).map(|to| to.parse::<usize>()) {
+                        None => self.add_watch(Watch::Pointer(from, from)),
+                        Some(Ok(to)) if to >= from => self.add_watch(Watch::Pointer(from, to)),
+                        Some(_) => println!("Expected a cell from {} on, found '{}'", from, words[2])
+                    }
+                },
+                (Some("uw"), Some(Ok(n))) | (Some("unwatch"), Some(Ok(n))) => {
+                    if n >= 1 && n <= self.watches.len() {
+                        let watch = self.watches.remove(n - 1);
+                        println!("Watchpoint {} removed ({})", n, watch);
+                    } else {
+                        println!("No watchpoint {}", n);
+                    }
+                },
+                (Some("i"), _) | (Some("info"), _) => self.info(),
+                (Some("t"), n) | (Some("tape"), n) => {
+                    let radius = match n { Some(Ok(n)) => n, _ => 8 };
+                    println!("{}", format_tape_window(self.vm.memory(), self.vm.data_ptr(), radius));
+                },
+                (Some("l"), n) | (Some("list"), n) => {
+                    let radius = match n { Some(Ok(n)) => n, _ => 4 };
+                    self.list(radius);
+                },
+                (Some("q"), _) | (Some("quit"), _) => return result,
+                (Some("h"), _) | (Some("help"), _) => println!("{}", HELP),
+                (Some(command), _) => println!("Unknown command '{}'. Type 'h' for help.", command)
+            }
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.vm.inst_ptr() >= self.tokens.len()
+    }
+
+    // Reports Ctrl-C stopping a step or continue. The program
+    //  can carry on from where it stopped.
+    fn stopped(&mut self, result : VMResult) -> VMResult {
+        if result != VMResult::Interrupted {
+            return result;
+        }
+        interrupt::clear();
+        // Move past the ^C
+        println!();
+        report::interrupted(&self.vm, &self.tokens, &self.source, "interpreter");
+        VMResult::Success
+    }
+
+    fn step(&mut self) -> VMResult {
+        self.watch_hit = false;
+        if self.finished() {
+            return VMResult::Success;
+        }
+        if interrupt::interrupted() {
+            return VMResult::Interrupted;
+        }
+
+        let access = StepAccess::before(&self.vm, self.tokens[self.vm.inst_ptr()].token_type);
+        let mut result = self.vm.step(&self.tokens);
+        if let Err(error) = self.vm.flush() {
+            if result == VMResult::Success {
+                result = VMResult::Error { message : format!("Unable to write output: {}", error), inst_ptr : None };
+            }
+        }
+        if let VMResult::Error { ref message, .. } = result {
+            println!("Error: {}", message);
+            return result;
+        }
+
+        for (i, watch) in self.watches.iter().enumerate() {
+            if let Some(message) = access.triggered(*watch, &self.vm) {
+                println!("Watchpoint {}: {}", i + 1, message);
+                self.watch_hit = true;
+            }
+        }
+        result
+    }
+
+    fn add_watch(&mut self, watch : Watch) {
+        self.watches.push(watch);
+        println!("Watchpoint {} set, {}", self.watches.len(), watch);
+    }
+
+    fn info(&self) {
+        if self.breakpoints.is_empty() && self.watches.is_empty() {
+            println!("No breakpoints or watchpoints");
+        }
+        for inst in &self.breakpoints {
+            println!("Breakpoint at {}", inst);
+        }
+        for (i, watch) in self.watches.iter().enumerate() {
+            println!("Watchpoint {}: {}", i + 1, watch);
+        }
+    }
+
+    fn show_current(&self) {
+        if self.finished() {
+            println!("Program finished, data pointer at {}", self.vm.data_ptr());
+        } else {
+            println!("{:>6}: {:?}    (data pointer {})",
+                self.vm.inst_ptr(), self.tokens[self.vm.inst_ptr()].token_type, self.vm.data_ptr());
+        }
+    }
+
+    fn list(&self, radius : usize) {
+        let current = self.vm.inst_ptr();
+        let start = current.saturating_sub(radius);
+        let end = (current + radius + 1).min(self.tokens.len());
+
+        for (i, token) in self.tokens.iter().enumerate().take(end).skip(start) {
+            let marker = if i == current { "=>" } else if self.breakpoints.contains(&i) { " *" } else { "  " };
+            println!("{} {:>6}: {:?}", marker, i, token.token_type);
+        }
+    }
+}
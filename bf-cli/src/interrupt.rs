@@ -0,0 +1,78 @@
+// Ctrl-C handling
+//
+// The first Ctrl-C sets a flag the VM checks, so the program
+//  stops cleanly and anything like --dump-tape still happens.
+//  A second Ctrl-C exits straight away, in case the program
+//  is stuck somewhere that doesn't check, such as waiting
+//  for input.
+
+use terminal;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub static INTERRUPTED : AtomicBool = AtomicBool::new(false);
+
+// Set once install has been called, until then Ctrl-C exits
+//  straight away.
+static INSTALLED : AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+mod sys {
+    pub const SIGINT : i32 = 2;
+
+    extern "C" {
+        pub fn signal(signum : i32, handler : extern "C" fn(i32)) -> usize;
+        pub fn siginterrupt(signum : i32, flag : i32) -> i32;
+        pub fn _exit(status : i32) -> !;
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_ : i32) {
+    // Only async signal safe calls can be made here
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        terminal::restore_from_signal();
+        unsafe { sys::_exit(130) };
+    }
+}
+
+// Catches SIGINT, it's left alone on other platforms. Reads
+//  that are waiting when it arrives fail with Interrupted
+//  rather than carrying on waiting, so a program stuck on
+//  ',' stops too.
+#[cfg(unix)]
+pub fn install() {
+    INSTALLED.store(true, Ordering::SeqCst);
+    unsafe {
+        sys::signal(sys::SIGINT, on_sigint);
+        sys::siginterrupt(sys::SIGINT, 1);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {
+    INSTALLED.store(true, Ordering::SeqCst);
+}
+
+pub fn installed() -> bool {
+    INSTALLED.load(Ordering::SeqCst)
+}
+
+// True once Ctrl-C has been pressed, until it's cleared.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// Lets the next Ctrl-C stop a program rather than exit, for
+//  the REPL and debugger which carry on after one is stopped.
+pub fn clear() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+// Used when Ctrl-C is read as a key by the line editor,
+//  where it doesn't raise a signal.
+pub fn interrupt() {
+    if !installed() || INTERRUPTED.swap(true, Ordering::SeqCst) {
+        terminal::exit_interrupted();
+    }
+}
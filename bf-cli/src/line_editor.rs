@@ -0,0 +1,189 @@
+// Line editing input
+//
+// When stdin is a terminal the program's input is read a
+//  whole line at a time, which can be edited with backspace
+//  and recalled from history with the up and down arrows.
+//  The line is then handed to ',' one byte at a time.
+
+use interrupt;
+use terminal::RawMode;
+
+use std::io;
+use std::io::prelude::*;
+
+const BACKSPACE : u8 = 0x7F;
+const CTRL_H : u8 = 0x08;
+const CTRL_C : u8 = 0x03;
+const CTRL_D : u8 = 0x04;
+const CTRL_U : u8 = 0x15;
+const ESCAPE : u8 = 0x1B;
+
+pub struct LineEditor {
+    // Bytes of the last line not yet read by the program
+    pending : Vec<u8>,
+
+    // Previous lines, oldest first
+    history : Vec<Vec<u8>>
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor {
+            pending : vec![],
+            history : vec![]
+        }
+    }
+
+    // Reads a line ending in '\n', or None at the end of input.
+    pub fn read_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let _raw = match RawMode::enable_without_signals() {
+            Ok(raw) => raw,
+            Err(_) => return read_plain_line()
+        };
+
+        let mut stdin = io::stdin();
+        let mut stdout = io::stdout();
+        stdout.flush()?;
+
+        let mut line : Vec<u8> = vec![];
+        // Position while looking back through the history,
+        //  and the line being typed before that started.
+        let mut history_pos = self.history.len();
+        let mut typed : Vec<u8> = vec![];
+
+        loop {
+            let byte = match read_byte(&mut stdin)? {
+                Some(byte) => byte,
+                None => return Ok(None)
+            };
+
+            match byte {
+                b'\r' | b'\n' => {
+                    stdout.write_all(b"\n")?;
+                    if !line.is_empty() {
+                        self.history.push(line.clone());
+                    }
+                    line.push(b'\n');
+                    return Ok(Some(line));
+                },
+                CTRL_D if line.is_empty() => return Ok(None),
+                // Ctrl-C is read as a key here rather than raising
+                //  a signal, so stop the program and end the input.
+                CTRL_C => {
+                    stdout.write_all(b"^C\n")?;
+                    interrupt::interrupt();
+                    return Ok(None);
+                },
+                BACKSPACE | CTRL_H => {
+                    if pop_char(&mut line) {
+                        stdout.write_all(b"\x08 \x08")?;
+                    }
+                },
+                CTRL_U => {
+                    erase(&mut stdout, &line)?;
+                    line.clear();
+                },
+                ESCAPE => {
+                    let replacement = match read_escape(&mut stdin)? {
+                        Some(b'A') if history_pos > 0 => {
+                            if history_pos == self.history.len() {
+                                typed = line.clone();
+                            }
+                            history_pos -= 1;
+                            self.history[history_pos].clone()
+                        },
+                        Some(b'B') if history_pos < self.history.len() => {
+                            history_pos += 1;
+                            if history_pos == self.history.len() { typed.clone() } else { self.history[history_pos].clone() }
+                        },
+                        _ => continue
+                    };
+                    erase(&mut stdout, &line)?;
+                    stdout.write_all(&replacement)?;
+                    line = replacement;
+                },
+                // Ignore any other control characters
+                0..=0x1F => continue,
+                _ => {
+                    line.push(byte);
+                    stdout.write_all(&[byte])?;
+                }
+            }
+            stdout.flush()?;
+        }
+    }
+}
+
+impl Read for LineEditor {
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.read_line()? {
+                Some(line) => self.pending = line,
+                None => return Ok(0)
+            }
+        }
+
+        let count = buf.len().min(self.pending.len());
+        buf[..count].copy_from_slice(&self.pending[..count]);
+        self.pending.drain(..count);
+        Ok(count)
+    }
+}
+
+fn read_byte(stdin : &mut io::Stdin) -> io::Result<Option<u8>> {
+    let mut buffer = [0u8; 1];
+    match stdin.read(&mut buffer)? {
+        0 => Ok(None),
+        _ => Ok(Some(buffer[0]))
+    }
+}
+
+// Reads the rest of an escape sequence, returning the final
+//  letter for arrow keys (ESC [ A) and None for anything else.
+fn read_escape(stdin : &mut io::Stdin) -> io::Result<Option<u8>> {
+    match read_byte(stdin)? {
+        Some(b'[') | Some(b'O') => (),
+        _ => return Ok(None)
+    }
+    // Skip any parameters, such as the 3 in ESC [ 3 ~
+    loop {
+        match read_byte(stdin)? {
+            Some(b'0'..=b'9') | Some(b';') => continue,
+            Some(b'A') => return Ok(Some(b'A')),
+            Some(b'B') => return Ok(Some(b'B')),
+            _ => return Ok(None)
+        }
+    }
+}
+
+// Removes the last character, which may be several bytes
+//  of UTF-8. Returns false if the line was already empty.
+fn pop_char(line : &mut Vec<u8>) -> bool {
+    while let Some(byte) = line.pop() {
+        if byte & 0xC0 != 0x80 {
+            return true;
+        }
+    }
+    false
+}
+
+// Clears the line from the screen, leaving the cursor where
+//  it started so anything before it on the line is kept.
+fn erase(stdout : &mut io::Stdout, line : &[u8]) -> io::Result<()> {
+    let width = String::from_utf8_lossy(line).chars().count();
+    if width > 0 {
+        write!(stdout, "\x1b[{}D\x1b[K", width)?;
+    }
+    Ok(())
+}
+
+// Used when the terminal can't be put into raw mode, the
+//  terminal still allows backspace but there's no history.
+fn read_plain_line() -> io::Result<Option<Vec<u8>>> {
+    io::stdout().flush()?;
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line)? {
+        0 => Ok(None),
+        _ => Ok(Some(line.into_bytes()))
+    }
+}
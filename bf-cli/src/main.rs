@@ -1,142 +1,793 @@
 // Load the main BF library
 extern crate bf_lib;
 
+// Command line parsing
+mod args;
+// Interactive debugger
+mod debugger;
+// Loading programs split over several files
+mod source;
+// Raw terminal input
+mod terminal;
+// Line editing for program input from a terminal
+mod line_editor;
+// Interactive REPL
+mod repl;
+// Stopping programs cleanly on Ctrl-C
+mod interrupt;
+// Reporting runtime errors and interrupts
+mod report;
+
 // Import the tools we need from BF library
 use bf_lib::traits::*;
-use bf_lib::bf_lexer::BFLexer;
+use bf_lib::bf_lexer::{BFLexer, LexSettings};
+use bf_lib::bf_lexer::BFToken;
 use bf_lib::bf_vm::BFVM;
 use bf_lib::bf_vm::VMSettings;
+use bf_lib::bf_vm::OutputMode;
+use bf_lib::bf_closure::ClosureProgram;
+use bf_lib::bf_bytecode::Bytecode;
+use bf_lib::bf_compiler;
+use bf_lib::bf_compiler::*;
+use bf_lib::bf_compiler::c::CCompiler;
+use bf_lib::bf_compiler::rust::RustCompiler;
+use bf_lib::bf_compiler::asm::{AsmCompiler, AsmSyntax};
+use bf_lib::bf_compiler::elf::ElfCompiler;
+use bf_lib::bf_compiler::wasm::WasmCompiler;
+use bf_lib::bf_compiler::llvm::LlvmCompiler;
 
 // Import from token printing script
 use bf_lib::bf_output::*;
 
+use args::*;
+use debugger::Debugger;
+use source::Source;
+use line_editor::LineEditor;
+use repl::Repl;
+use terminal::{RawMode, RawInput};
+use bf_lib::bf_throttle::{Speed, SpeedUnit};
+use bf_lib::bf_lexer::BFTokenType;
+use bf_lib::bf_tape::TapeImage;
+use bf_lib::bf_record::{InputLog, Replay, RECORD_HEADER};
+
 // Import various STD library components
 use std::process;
 use std::fs::File;
+use std::io;
+use std::io::IsTerminal;
 use std::io::prelude::*;
 
-// Command line arguments are parsed using a simpel state machine
-#[derive(Debug)]
-enum ArgumentMode {
-    Skip,
-    Start,
-    Str,
-    File,
-    Dump,
-}
+/* ---------------------------------------------------.
+|     Exit Codes                                      |
+'---------------------------------------------------- */
 
-// Reads a file and puts the contents into the out_str String.
-fn read_file(in_str : String, mut out_str : &mut String ) {
-    // TODO: Return an code here if needed and then print out
-    //  a usable error message.
-    // Currently panics on failure (expect)!
-    let mut file = File::open(in_str).expect("Unable to open file.");
-    file.read_to_string(&mut out_str).expect("Unable to read file.");
-}
+// The BF program ran (or compiled) successfully
+const EXIT_SUCCESS : i32 = 0;
+// The BF program failed while running, or couldn't be compiled
+const EXIT_RUNTIME_ERROR : i32 = 1;
+// The command line arguments were invalid
+const EXIT_USAGE_ERROR : i32 = 2;
+// The BF program couldn't be parsed, e.g. unmatched brackets
+const EXIT_PARSE_ERROR : i32 = 3;
+// A file couldn't be read or written
+const EXIT_IO_ERROR : i32 = 4;
+// The program was stopped with Ctrl-C
+const EXIT_INTERRUPTED : i32 = 130;
+
+/* ---------------------------------------------------.
+|     Subcommands                                     |
+'---------------------------------------------------- */
+
+// Options for choosing the program, shared by all subcommands
+const STR : Opt = Opt { short : Some("-s"), long : "--str", value : Some("bfstring"), help : "Use the given string as the program." };
+const FILE : Opt = Opt { short : Some("-f"), long : "--file", value : Some("file"), help : "Read the program from a file, '-' for stdin." };
+const USERMODE : Opt = Opt { short : Some("-u"), long : "--usermode", value : None, help : "Input is prompted for one character at a time." };
+
+// Options for where the program's input comes from
+const INPUT : Opt = Opt { short : Some("-i"), long : "--input", value : Some("file"), help : "Read the program's input from a file." };
+const INPUT_STRING : Opt = Opt { short : None, long : "--input-string", value : Some("text"), help : "Use text as the program's input, \\n, \\t, \\0 and \\xHH escapes are expanded." };
+// Options for where the program's output goes
+const OUT : Opt = Opt { short : Some("-o"), long : "--out", value : Some("out_file"), help : "Write the program's output to a file." };
+const OUTPUT_MODE : Opt = Opt { short : Some("-m"), long : "--output-mode", value : Some("mode"), help : "How output is written: raw (default), decimal, hex or utf8." };
+const NO_LINE_EDIT : Opt = Opt { short : None, long : "--no-line-edit", value : None, help : "Read input from a terminal as it is typed, without line editing." };
+// Options for the starting tape
+const TAPE : Opt = Opt { short : None, long : "--tape", value : Some("file"), help : "Load the starting tape from a text file of cell values." };
+const TAPE_RAW : Opt = Opt { short : None, long : "--tape-raw", value : Some("file"), help : "Load the starting tape from a file of raw bytes, one per cell." };
+const START : Opt = Opt { short : None, long : "--start", value : Some("cell"), help : "Cell the data pointer starts on (default 30000)." };
+const DEBUG_HASH : Opt = Opt { short : None, long : "--debug-hash", value : None, help : "A '#' in the source prints the cells around the data pointer to stderr." };
+const DBFI : Opt = Opt { short : None, long : "--dbfi", value : None, help : "A '!' in the source ends the program, the rest is its input." };
+
+const RUN : Command = Command {
+    name : "run",
+    about : "Runs a BF program.",
+    positional : Some("<file>..."),
+    options : &[
+        STR, FILE, USERMODE, INPUT, INPUT_STRING, NO_LINE_EDIT, DBFI, DEBUG_HASH, OUT, OUTPUT_MODE, TAPE, TAPE_RAW, START,
+        Opt { short : None, long : "--raw", value : None, help : "Read keys as they're pressed, without waiting for Enter or echoing them." },
+        Opt { short : None, long : "--no-key", value : Some("value"), help : "With --raw, ',' doesn't wait for a key and stores value if none was pressed." },
+        Opt { short : None, long : "--speed", value : Some("rate"), help : "Run at most rate instructions a second. Use + and - to change it and space to pause." },
+        Opt { short : None, long : "--per-output", value : None, help : "With --speed, the rate is in outputs ('.') a second instead." },
+        Opt { short : None, long : "--dump-tape", value : None, help : "Show the used part of the tape when the program ends, fails or is stopped with Ctrl-C." },
+        Opt { short : None, long : "--dump-tape-file", value : Some("file"), help : "Write the whole tape to a file as raw bytes when the program ends." },
+        Opt { short : None, long : "--record", value : Some("file"), help : "Log every byte the program reads, with the instruction count it was read at." },
+        Opt { short : None, long : "--replay", value : Some("file"), help : "Feed the program the input from a --record log, stopping if it reads at a different point." },
+        Opt { short : Some("-e"), long : "--engine", value : Some("engine"), help : "How to run the program: interpreter (default), closures or bytecode." },
+    ]
+};
+
+const DUMP : Command = Command {
+    name : "dump",
+    about : "Prints the program's tokens in an optimised assembly like format.",
+    positional : Some("<file>..."),
+    options : &[
+        STR, FILE,
+        Opt { short : Some("-o"), long : "--out", value : Some("out_file"), help : "Also write the dump to a file." },
+    ]
+};
+
+const CHECK : Command = Command {
+    name : "check",
+    about : "Checks a BF program parses without running it.",
+    positional : Some("<file>..."),
+    options : &[ STR, FILE ]
+};
+
+const COMPILE : Command = Command {
+    name : "compile",
+    about : "Compiles a BF program ahead of time.",
+    positional : Some("<file>..."),
+    options : &[
+        STR, FILE,
+        Opt { short : Some("-t"), long : "--target", value : Some("target"), help : "c (default), rust, asm (x86-64 GNU as), nasm, elf, wasm, wat or llvm." },
+        Opt { short : Some("-o"), long : "--out", value : Some("out_file"), help : "Where to write the compiled program (default stdout)." },
+        Opt { short : None, long : "--tape-size", value : Some("cells"), help : "Number of cells on the tape (default 60000)." },
+        Opt { short : None, long : "--cell-width", value : Some("bits"), help : "Cell size: 8, 16 or 32 (default 8)." },
+        Opt { short : None, long : "--eof", value : Some("behaviour"), help : "Value stored on end of input: 0, -1 or unchanged (default 0)." },
+        Opt { short : None, long : "--unchecked", value : None, help : "Rust: skip bounds checks on tape access." },
+        Opt { short : None, long : "--module", value : None, help : "Rust: leave out main so the output can be used as a module." },
+    ]
+};
+
+const FMT : Command = Command {
+    name : "fmt",
+    about : "Reformats a BF program with each loop indented. Comments are removed.",
+    positional : Some("<file>..."),
+    options : &[
+        STR, FILE,
+        Opt { short : Some("-o"), long : "--out", value : Some("out_file"), help : "Where to write the formatted program (default stdout)." },
+        Opt { short : Some("-w"), long : "--width", value : Some("columns"), help : "Maximum line width (default 80)." },
+    ]
+};
+
+const DEBUG : Command = Command {
+    name : "debug",
+    about : "Steps through a BF program interactively.",
+    positional : Some("<file>..."),
+    options : &[ STR, FILE, USERMODE, INPUT, INPUT_STRING, NO_LINE_EDIT, DBFI, DEBUG_HASH, OUT, OUTPUT_MODE, TAPE, TAPE_RAW, START ]
+};
+
+const REPL : Command = Command {
+    name : "repl",
+    about : "Runs BF code line by line, keeping the tape between lines.",
+    positional : None,
+    options : &[ USERMODE, OUTPUT_MODE, TAPE, TAPE_RAW, START ]
+};
+
+const COMMANDS : [&Command; 7] = [&RUN, &DUMP, &CHECK, &COMPILE, &FMT, &DEBUG, &REPL];
 
 fn print_help() {
     println!("
 Rust BrainFuck Interpreter
 
 Usage:
-    bf-cli <file>
-    bf-cli <file> -u
-    bf-cli ( -f | --file ) <file>
-    bf-cli ( -s | --str ) <bfstring>
-
-Options:
-    -h --help                Shows this screen.
-    -u --usermode            Input is prompted for.
-    -d --dumpout <out_file>  Dumps the bf out in an optimised format
+    bf-cli <command> [options] <file>
+    bf-cli <file>                        Same as 'bf-cli run <file>'
+
+Commands:
+    run        Runs a BF program.
+    dump       Prints the program's tokens in an optimised format.
+    check      Checks a BF program parses without running it.
+    compile    Compiles a BF program ahead of time.
+    fmt        Reformats a BF program.
+    debug      Steps through a BF program interactively.
+    repl       Runs BF code line by line, keeping the tape between lines.
+
+Run 'bf-cli <command> --help' for the options of each command.
+
+Exit codes:
+    0          Success
+    1          The program failed while running or couldn't be compiled
+    2          Invalid command line usage
+    3          The program couldn't be parsed
+    4          A file couldn't be read or written
+    130        The program was stopped with Ctrl-C
 ");
 }
 
-fn main() {
-    /* ---------------------------------------------------.
-    |     Load Arguments                                  |
-    '---------------------------------------------------- */
+// Prints a usage error and exits.
+fn usage_error(command : &Command, message : &str) -> ! {
+    eprintln!("Error: {}", message);
+    eprintln!("Try 'bf-cli {} --help' for more information.", command.name);
+    process::exit(EXIT_USAGE_ERROR);
+}
 
-    // Argument Setup - Skip first argument as it's the binary location.
-    let mut mode = ArgumentMode::Skip;
-    let mut input = String::new();
-    let mut dump_out = false;
-    let mut dump_out_file = String::new();
-    let mut settings = VMSettings::new();
+// Prints why a file couldn't be used and exits.
+fn file_error(action : &str, file_name : &str, error : io::Error) -> ! {
+    // Whatever was reading the output has stopped, e.g.
+    //  'bf-cli dump prog.bf | head', which is fine
+    if error.kind() == io::ErrorKind::BrokenPipe {
+        process::exit(EXIT_SUCCESS);
+    }
+    eprintln!("Error: Unable to {} '{}': {}", action, file_name, error);
+    process::exit(EXIT_IO_ERROR);
+}
 
-    // Loop through each argument and set various settings as per the state
-    // Aka if in Str mode the next argument will be considered the input.
-    for argument in std::env::args() {
-        use self::ArgumentMode::*;
-        match mode {
-            Skip => mode = Start,
-            Start => { 
-                match argument.as_ref() {
-                    "-s" | "--str" => mode = Str,
-                    "-f" | "--file" => mode = File,
-                    "-h" | "--help" => {
-                        print_help();
-                        process::exit(1);
-                    },
-                    "-u" | "--usermode" => settings.prompt_for_input = true,
-                    "-d" | "--dumpout" => mode = Dump,
-                    _ => read_file(argument, &mut input)
-                };
-            },
-            Str => {
-                input = argument;
-                mode = Start;
-            },
-            File => { 
-                read_file(argument, &mut input);
-                mode = Start;
-            },
-            Dump => {
-                dump_out = true;
-                dump_out_file = argument;
-                mode = Start;
+// Loads the program source from --str, or --file followed by
+//  any positional files, which are joined in order.
+fn load_source(command : &Command, args : &Args) -> Source {
+    let mut files : Vec<String> = vec![];
+    if let Some(file) = args.value("--file") {
+        files.push(String::from(file));
+    }
+    files.extend(args.positional.iter().cloned());
+
+    match args.value("--str") {
+        Some(_) if !files.is_empty() => usage_error(command, "--str can't be used together with files"),
+        Some(program) => Source::from_string(program),
+        None if files.is_empty() => usage_error(command, "No program given, expected a file or --str"),
+        None => {
+            if files.iter().filter(|file| *file == source::STDIN).count() > 1 {
+                usage_error(command, "stdin ('-') can only be read once");
             }
+            Source::load(&files).unwrap_or_else(|(file_name, error)| file_error("read", &file_name, error))
         }
     }
+}
 
+// Parses the source, exiting with an error if it's invalid.
+fn parse_source(source : &Source, settings : &LexSettings) -> Vec<BFToken> {
+    match BFLexer::parse_with(source.text.clone(), settings) {
+        LexResult::Success(tokens) => tokens,
+        LexResult::Error { message, pos } => {
+            eprintln!("{}: Parse error: {}", source.locate(pos), message);
+            process::exit(EXIT_PARSE_ERROR);
+        }
+    }
+}
 
-    /* ---------------------------------------------------.
-    |    Interpret and Run Input                          |
-    '---------------------------------------------------- */
+// Language extensions turned on by the command line.
+fn lex_settings(args : &Args) -> LexSettings {
+    let mut settings = LexSettings::new();
+    settings.debug_token = args.flag("--debug-hash");
+    settings
+}
 
-    // Parse string input into Vector of BFTokens
-    // This step also matches brackets up to each other
-    let tokens = BFLexer::parse(String::from(input));
+// Works out where the program's input comes from, None
+//  means stdin. With --dbfi this also removes the input
+//  from the end of the source. Input typed into a terminal
+//  is read a line at a time with editing, unless -u asks
+//  for a prompt on every ','.
+fn program_input(command : &Command, args : &Args, source : &mut Source) -> Option<Box<dyn Read>> {
+    if args.flag("--raw") {
+        return Some(raw_input(command, args, source));
+    }
+
+    let dbfi = if args.flag("--dbfi") { source.split_input() } else { None };
+
+    match (args.value("--input"), args.value("--input-string"), dbfi) {
+        (Some(file_name), None, None) => {
+            let file = File::open(file_name).unwrap_or_else(|error| file_error("read", file_name, error));
+            Some(Box::new(io::BufReader::new(file)))
+        },
+        (None, Some(text), None) => {
+            let bytes = unescape(text).unwrap_or_else(|message| usage_error(command, &message));
+            Some(Box::new(io::Cursor::new(bytes)))
+        },
+        (None, None, Some(data)) => Some(Box::new(io::Cursor::new(data.into_bytes()))),
+        (None, None, None) if source.uses_stdin() => Some(terminal_input()),
+        (None, None, None) if io::stdin().is_terminal() && !args.flag("--usermode") && !args.flag("--no-line-edit") => {
+            Some(Box::new(LineEditor::new()))
+        },
+        (None, None, None) => None,
+        _ => usage_error(command, "Only one of --input, --input-string or '!' input in the source can be used")
+    }
+}
+
+// Input for --raw, which has to come from the terminal.
+fn raw_input(command : &Command, args : &Args, source : &Source) -> Box<dyn Read> {
+    let other_input = ["--usermode", "--input", "--input-string", "--no-line-edit", "--dbfi"].iter().find(|opt| args.flag(opt));
+    if let Some(opt) = other_input {
+        usage_error(command, &format!("--raw can't be used with {}", opt));
+    }
+    if source.uses_stdin() || !io::stdin().is_terminal() {
+        usage_error(command, "--raw needs stdin to be a terminal");
+    }
+
+    let no_key = args.value("--no-key").map(|value| {
+        match value.parse::<i16>() {
+            Ok(number) if (-128..=255).contains(&number) => number as u8,
+            _ => usage_error(command, "--no-key must be a number from -128 to 255")
+        }
+    });
+
+    Box::new(RawInput { no_key })
+}
+
+// Sets up the VM with the input and output options given.
+fn create_vm(command : &Command, args : &Args, input : Option<Box<dyn Read>>) -> BFVM {
+    let mut settings = VMSettings::new();
+    settings.prompt_for_input = args.flag("--usermode");
+    if let Some(rate) = args.value("--speed") {
+        let rate = rate.parse().unwrap_or_else(|_| usage_error(command, "Speed must be a number"));
+        let unit = if args.flag("--per-output") { SpeedUnit::Output } else { SpeedUnit::Instructions };
+        settings.speed = Some(Speed::new(rate, unit));
+    }
+    if interrupt::installed() {
+        settings.interrupt = Some(&interrupt::INTERRUPTED);
+    }
+    if let Some(mode) = args.value("--output-mode") {
+        settings.output_mode = OutputMode::from_name(mode)
+            .unwrap_or_else(|| usage_error(command, "Output mode must be raw, decimal, hex or utf8"));
+    }
 
     // Create a new VM instance
     let mut bfvm = BFVM::new(settings);
+    if let Some(input) = input {
+        bfvm.set_input(input);
+    }
+    if let Some(file_name) = args.value("--out") {
+        let file = File::create(file_name).unwrap_or_else(|error| file_error("create", file_name, error));
+        bfvm.set_output(Box::new(io::BufWriter::new(file)));
+    }
+
+    load_tape(command, args, &mut bfvm);
+
+    bfvm
+}
+
+// Fills the tape from --tape or --tape-raw and moves the data
+//  pointer to --start.
+fn load_tape(command : &Command, args : &Args, bfvm : &mut BFVM) {
+    let start = match args.value("--start") {
+        Some(cell) => match cell.parse::<usize>() {
+            Ok(cell) if cell < bfvm.memory().len() => Some(cell),
+            _ => usage_error(command, &format!("--start must be a cell from 0 to {}", bfvm.memory().len() - 1))
+        },
+        None => None
+    };
+    let first_cell = start.unwrap_or(bfvm.data_ptr());
+
+    let image = match (args.value("--tape"), args.value("--tape-raw")) {
+        (Some(_), Some(_)) => usage_error(command, "Only one of --tape and --tape-raw can be used"),
+        (Some(file_name), None) => {
+            let mut text = String::new();
+            if let Err(error) = File::open(file_name).and_then(|mut file| file.read_to_string(&mut text)) {
+                file_error("read", file_name, error);
+            }
+            match TapeImage::from_text(&text, first_cell) {
+                Ok(image) => Some((file_name, image)),
+                Err(message) => {
+                    eprintln!("{}: Tape error: {}", file_name, message);
+                    process::exit(EXIT_PARSE_ERROR);
+                }
+            }
+        },
+        (None, Some(file_name)) => {
+            let mut bytes = vec![];
+            if let Err(error) = File::open(file_name).and_then(|mut file| file.read_to_end(&mut bytes)) {
+                file_error("read", file_name, error);
+            }
+            // A whole tape, e.g. from --dump-tape-file, goes back
+            //  where it came from.
+            let first_cell = if bytes.len() == bfvm.memory().len() { 0 } else { first_cell };
+            Some((file_name, TapeImage::from_bytes(&bytes, first_cell)))
+        },
+        (None, None) => None
+    };
+
+    if let Some((file_name, image)) = image {
+        if let Err(message) = image.load(bfvm) {
+            eprintln!("{}: Tape error: {}", file_name, message);
+            process::exit(EXIT_PARSE_ERROR);
+        }
+    }
+
+    // --start wins over a data pointer marked in the file
+    if let Some(cell) = start {
+        bfvm.set_data_ptr(cell);
+    }
+}
 
-    // If parsing was successful run the script, 
-    //  otherwise Error
-    // Currently parsing can't fail.
-    let result = match tokens {
-        LexResult::Success(t) => {
-            if dump_out {
-                dump_tokens(t.clone(), dump_out_file);
+// Sets up --record or --replay, which only the interpreter
+//  can do as it's the only engine counting instructions.
+fn input_log(command : &Command, args : &Args, engine : &str) -> Option<InputLog> {
+    let (record, replay) = (args.value("--record"), args.value("--replay"));
+    if (record.is_some() || replay.is_some()) && engine != "interpreter" {
+        usage_error(command, "--record and --replay can only be used with the interpreter engine");
+    }
+
+    match (record, replay) {
+        (Some(_), Some(_)) => usage_error(command, "Only one of --record and --replay can be used"),
+        (Some(file_name), None) => {
+            let mut file = File::create(file_name).unwrap_or_else(|error| file_error("create", file_name, error));
+            if let Err(error) = writeln!(file, "{}", RECORD_HEADER) {
+                file_error("write to", file_name, error);
+            }
+            Some(InputLog::Record(Box::new(io::BufWriter::new(file))))
+        },
+        (None, Some(file_name)) => {
+            let other_input = ["--usermode", "--input", "--input-string", "--dbfi", "--raw"].iter().find(|opt| args.flag(opt));
+            if let Some(opt) = other_input {
+                usage_error(command, &format!("--replay can't be used with {}", opt));
+            }
+
+            let mut text = String::new();
+            if let Err(error) = File::open(file_name).and_then(|mut file| file.read_to_string(&mut text)) {
+                file_error("read", file_name, error);
+            }
+            match Replay::parse(&text) {
+                Ok(replay) => Some(InputLog::Replay(replay)),
+                Err(message) => {
+                    eprintln!("{}: Replay error: {}", file_name, message);
+                    process::exit(EXIT_PARSE_ERROR);
+                }
+            }
+        },
+        (None, None) => None
+    }
+}
+
+// When the program itself was piped in on stdin its input
+//  comes from the terminal instead. If there's no terminal
+//  the program sees the end of input straight away.
+fn terminal_input() -> Box<dyn Read> {
+    let terminal = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    match File::open(terminal) {
+        Ok(file) => Box::new(file),
+        Err(_) => Box::new(io::empty())
+    }
+}
+
+// Writes output to a file if one was given, otherwise stdout.
+fn write_output(out_file : Option<&str>, data : &[u8]) -> Option<File> {
+    match out_file {
+        Some(file_name) => {
+            let mut file = File::create(file_name).unwrap_or_else(|error| file_error("create", file_name, error));
+            if let Err(error) = file.write_all(data) {
+                file_error("write to", file_name, error);
+            }
+            Some(file)
+        },
+        None => {
+            if let Err(error) = io::stdout().write_all(data) {
+                file_error("write to", "STDOUT", error);
             }
-            bfvm.run(t)
+            None
         }
-        _ =>  {
-            println!("Error !");
-            VMResult::Error { message : String::from("Uh oh") }
+    }
+}
+
+/* ---------------------------------------------------.
+|     Interpret and Run Input                         |
+'---------------------------------------------------- */
+
+fn run(args : Args) -> VMResult {
+    let engine = args.value("--engine").unwrap_or("interpreter");
+    if !["interpreter", "closures", "bytecode"].contains(&engine) {
+        usage_error(&RUN, &format!("Unknown engine '{}'", engine));
+    }
+
+    if args.value("--speed").is_some() && engine != "interpreter" {
+        usage_error(&RUN, "--speed can only be used with the interpreter engine");
+    }
+    if args.flag("--per-output") && args.value("--speed").is_none() {
+        usage_error(&RUN, "--per-output can only be used with --speed");
+    }
+
+    // Stop cleanly on Ctrl-C so where the program got to can
+    //  be shown and the tape can still be dumped
+    interrupt::install();
+
+    let log = input_log(&RUN, &args, engine);
+    let mut source = load_source(&RUN, &args);
+    let input = program_input(&RUN, &args, &mut source);
+    let input_is_stdin = match input {
+        None => true,
+        Some(_) => args.value("--input").is_none() && args.value("--input-string").is_none() && !args.flag("--dbfi")
+    };
+
+    // Parse string input into Vector of BFTokens
+    // This step also matches brackets up to each other
+    let tokens = parse_source(&source, &lex_settings(&args));
+
+    let mut bfvm = create_vm(&RUN, &args, input);
+    if let Some(log) = log {
+        bfvm.set_input_log(log);
+    }
+
+    if args.value("--no-key").is_some() && !args.flag("--raw") {
+        usage_error(&RUN, "--no-key can only be used with --raw");
+    }
+
+    // The speed can be changed with keys unless the program
+    //  is reading them itself.
+    let reads_keys = args.flag("--raw") || (input_is_stdin && tokens.iter().any(|token| token.token_type == BFTokenType::Input));
+    let control_speed = match bfvm.speed() {
+        Some(speed) if !reads_keys && io::stdin().is_terminal() => Some(speed.clone()),
+        _ => None
+    };
+
+    // Only in raw mode while the program runs, it's
+    //  restored when this is dropped.
+    let raw = if !args.flag("--raw") && control_speed.is_none() {
+        None
+    } else {
+        let mode = if args.value("--no-key").is_some() { RawMode::enable_nonblocking() } else { RawMode::enable() };
+        match mode {
+            Ok(mode) => Some(mode),
+            Err(error) => {
+                eprintln!("Error: Unable to put the terminal into raw mode: {}", error);
+                process::exit(EXIT_IO_ERROR);
+            }
         }
     };
 
+    if let Some(speed) = control_speed {
+        terminal::control_speed(speed);
+    }
+
+    let result = match engine {
+        "closures" => ClosureProgram::compile(&tokens).run(&mut bfvm),
+        "bytecode" => match Bytecode::compile(&tokens) {
+            Ok(bytecode) => bfvm.run_bytecode(&bytecode),
+            Err(message) => VMResult::Error { message, inst_ptr : None }
+        },
+        _ => bfvm.run(tokens.clone())
+    };
+
+    drop(raw);
+
+    // Output going to something like 'head' that stops
+    //  reading early isn't an error, the program just stops.
+    let result = if bfvm.output_closed() { VMResult::Success } else { result };
+
     // In case there was no new line printed
     //  print one now before exit so prompt is on
     //  new line if running from command line.
-    println!();
+    if args.value("--out").is_none() && !bfvm.output_closed() {
+        let _ = writeln!(io::stdout());
+    }
+
+    if let VMResult::Error { ref message, inst_ptr } = result {
+        report::error(message, inst_ptr, &bfvm, &tokens, &source);
+    }
+    if result == VMResult::Interrupted {
+        report::interrupted(&bfvm, &tokens, &source, engine);
+    }
+
+    if let Some(InputLog::Replay(replay)) = bfvm.input_log() {
+        if replay.remaining() > 0 && result == VMResult::Success {
+            eprintln!("Warning: The program finished without reading the last {} recorded input(s)", replay.remaining());
+        }
+    }
+
+    if args.flag("--dump-tape") {
+        eprintln!("{}", format_tape_dump(bfvm.memory(), bfvm.data_ptr()));
+    }
+    if let Some(file_name) = args.value("--dump-tape-file") {
+        let tape : Vec<u8> = bfvm.memory().iter().map(|cell| *cell as u8).collect();
+        if let Err(error) = File::create(file_name).and_then(|mut file| file.write_all(&tape)) {
+            file_error("write tape to", file_name, error);
+        }
+    }
+
+    result
+}
+
+fn dump(args : Args) {
+    let tokens = parse_source(&load_source(&DUMP, &args), &LexSettings::new());
+
+    match args.value("--out") {
+        Some(file_name) => {
+            if let Err(error) = dump_tokens(tokens, String::from(file_name)) {
+                file_error("write dump to", file_name, error);
+            }
+        },
+        None => {
+            write_output(None, format!("{}\n", format_tokens(tokens)).as_bytes());
+        }
+    }
+}
+
+fn check(args : Args) {
+    let tokens = parse_source(&load_source(&CHECK, &args), &LexSettings::new());
+    println!("OK: {} instructions", tokens.len());
+}
+
+// Compiles the tokens with the chosen backend and writes out the result.
+fn compile(args : Args) {
+    let target = args.value("--target").unwrap_or("c");
+    if !["c", "rust", "asm", "nasm", "elf", "wasm", "wat", "llvm"].contains(&target) {
+        usage_error(&COMPILE, &format!("Unknown target '{}'", target));
+    }
+
+    if target != "rust" {
+        for flag in &["--unchecked", "--module"] {
+            if args.flag(flag) {
+                usage_error(&COMPILE, &format!("{} can only be used with --target rust", flag));
+            }
+        }
+    }
+
+    let mut settings = CompileSettings::new();
+    if let Some(size) = args.value("--tape-size") {
+        settings.tape_size = size.parse().unwrap_or_else(|_| usage_error(&COMPILE, "Tape size must be a number"));
+    }
+    if let Some(bits) = args.value("--cell-width") {
+        settings.cell_width = CellWidth::from_bits(bits).unwrap_or_else(|| usage_error(&COMPILE, "Cell width must be 8, 16 or 32"));
+    }
+    if let Some(eof) = args.value("--eof") {
+        settings.eof = EofBehaviour::from_name(eof).unwrap_or_else(|| usage_error(&COMPILE, "EOF behaviour must be 0, -1 or unchanged"));
+    }
+
+    let tokens = parse_source(&load_source(&COMPILE, &args), &LexSettings::new());
+
+    let result = match target {
+        "c" => bf_compiler::compile(&CCompiler::new(settings), tokens),
+        "rust" => {
+            let mut compiler = RustCompiler::new(settings);
+            compiler.unchecked = args.flag("--unchecked");
+            compiler.module = args.flag("--module");
+            bf_compiler::compile(&compiler, tokens)
+        },
+        "asm" => bf_compiler::compile(&AsmCompiler::new(settings), tokens),
+        "nasm" => {
+            let mut compiler = AsmCompiler::new(settings);
+            compiler.syntax = AsmSyntax::Nasm;
+            bf_compiler::compile(&compiler, tokens)
+        },
+        "elf" => bf_compiler::compile(&ElfCompiler::new(settings), tokens),
+        "wasm" => bf_compiler::compile(&WasmCompiler::new(settings), tokens),
+        "wat" => {
+            let mut compiler = WasmCompiler::new(settings);
+            compiler.text = true;
+            bf_compiler::compile(&compiler, tokens)
+        },
+        _ => bf_compiler::compile(&LlvmCompiler::new(settings), tokens)
+    };
+
+    match result {
+        CompileResult::Success(code) => {
+            let file = write_output(args.value("--out"), &code);
+            if let (Some(file), Some(file_name), "elf") = (file, args.value("--out"), target) {
+                if let Err(error) = make_executable(&file) {
+                    file_error("make executable", file_name, error);
+                }
+            }
+        },
+        CompileResult::Error { message } => {
+            eprintln!("Compile error: {}", message);
+            process::exit(EXIT_RUNTIME_ERROR);
+        }
+    }
+}
+
+// Marks a compiled binary as executable.
+#[cfg(unix)]
+fn make_executable(file : &File) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = file.metadata()?.permissions();
+    permissions.set_mode(permissions.mode() | 0o111);
+    file.set_permissions(permissions)
+}
+
+#[cfg(not(unix))]
+fn make_executable(_file : &File) -> io::Result<()> {
+    Ok(())
+}
+
+fn fmt(args : Args) {
+    let width = match args.value("--width") {
+        Some(width) => width.parse().unwrap_or_else(|_| usage_error(&FMT, "Width must be a number")),
+        None => 80
+    };
+
+    let source = load_source(&FMT, &args);
+
+    // Check the brackets match before reformatting
+    parse_source(&source, &LexSettings::new());
+
+    write_output(args.value("--out"), format_source(&source.text, width).as_bytes());
+}
+
+fn debug(args : Args) -> VMResult {
+    let mut source = load_source(&DEBUG, &args);
+    if source.uses_stdin() {
+        usage_error(&DEBUG, "The program can't be read from stdin ('-') as debugger commands are read from there");
+    }
+    let input = program_input(&DEBUG, &args, &mut source);
+    let tokens = parse_source(&source, &lex_settings(&args));
+
+    // Ctrl-C stops the program and goes back to the prompt
+    interrupt::install();
+
+    Debugger::new(create_vm(&DEBUG, &args, input), tokens, source).run()
+}
+
+fn repl(args : Args) -> VMResult {
+    // Lines for ',' are edited like REPL lines when typed in
+    let input : Option<Box<dyn Read>> = if io::stdin().is_terminal() && !args.flag("--usermode") {
+        Some(Box::new(LineEditor::new()))
+    } else {
+        None
+    };
+
+    // Ctrl-C stops the line that's running and goes back to
+    //  the prompt
+    interrupt::install();
+
+    match Repl::new(create_vm(&REPL, &args, input)).run() {
+        Ok(result) => result,
+        Err(error) => {
+            eprintln!("Error: Unable to read from STDIN: {}", error);
+            process::exit(EXIT_IO_ERROR);
+        }
+    }
+}
+
+fn main() {
+    /* ---------------------------------------------------.
+    |     Load Arguments                                  |
+    '---------------------------------------------------- */
+
+    // Skip first argument as it's the binary location.
+    let arguments : Vec<String> = std::env::args().skip(1).collect();
+
+    // Find the subcommand, anything else is taken as
+    //  'run' so 'bf-cli <file>' still works.
+    let (command, rest) = match arguments.first().map(|a| a.as_ref()) {
+        None | Some("-h") | Some("--help") | Some("help") => {
+            match arguments.get(1).and_then(|name| COMMANDS.iter().find(|c| c.name == name)) {
+                Some(command) => println!("{}", command.help()),
+                None => print_help()
+            }
+            process::exit(EXIT_SUCCESS);
+        },
+        Some(name) => match COMMANDS.iter().find(|c| c.name == name) {
+            Some(command) => (*command, &arguments[1..]),
+            None => (&RUN, &arguments[..])
+        }
+    };
+
+    if rest.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", command.help());
+        process::exit(EXIT_SUCCESS);
+    }
+
+    let args = match command.parse(rest) {
+        Ok(args) => args,
+        Err(message) => usage_error(command, &message)
+    };
 
     /* ---------------------------------------------------.
     |    Output Result                                    |
     '---------------------------------------------------- */
 
-    // Setup exit code - 0 = Success
-    // Currently all error's except panics are 1
-    let return_code = if result == VMResult::Success { 0 } else { 1 };
+    let result = match command.name {
+        "run" => run(args),
+        "dump" => { dump(args); VMResult::Success },
+        "check" => { check(args); VMResult::Success },
+        "compile" => { compile(args); VMResult::Success },
+        "fmt" => { fmt(args); VMResult::Success },
+        "repl" => repl(args),
+        _ => debug(args)
+    };
+
+    // Runtime errors have already been printed
+    let return_code = match result {
+        VMResult::Success => EXIT_SUCCESS,
+        VMResult::Interrupted => EXIT_INTERRUPTED,
+        VMResult::Error { .. } => EXIT_RUNTIME_ERROR
+    };
     process::exit(return_code);
 }
@@ -0,0 +1,215 @@
+// Interactive REPL
+//
+// Each line entered is lexed and run against the same VM,
+//  so the tape and data pointer carry over between lines.
+//  Lines starting with ':' are commands for the REPL.
+
+use bf_lib::traits::*;
+use bf_lib::bf_lexer::{BFLexer, BFToken};
+use bf_lib::bf_vm::BFVM;
+use bf_lib::bf_output::format_tape_window;
+
+use interrupt;
+use line_editor::LineEditor;
+use report;
+use source::Source;
+
+use std::cell::Cell;
+use std::io;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+const HELP : &str = "
+Enter BF code to run it, the tape is kept between lines.
+A line with an unclosed '[' continues on the next line.
+
+Commands:
+    :t, :tape [radius]  Show the cells around the data pointer (default 8)
+    :r, :reset          Clear the tape and move the data pointer back to the start
+    :l, :load <file>    Run a BF file against the current tape
+    :u, :undo           Undo the last line, reset or load
+    :q, :quit           Leave the REPL (or press Ctrl-D)
+    :h, :help           Shows this screen.
+";
+
+// How many lines can be undone
+const UNDO_LIMIT : usize = 100;
+
+// Tape and data pointer from before a line was run
+struct Snapshot {
+    mem : Vec<i8>,
+    data_ptr : usize
+}
+
+// Passes output on to stdout, remembering the last byte so
+//  the prompt can go on a new line.
+struct TrackedOutput {
+    last : Rc<Cell<u8>>
+}
+
+impl Write for TrackedOutput {
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+        let count = io::stdout().write(buf)?;
+        if count > 0 {
+            self.last.set(buf[count - 1]);
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+pub struct Repl {
+    vm : BFVM,
+    editor : LineEditor,
+    undo : Vec<Snapshot>,
+    last_output : Rc<Cell<u8>>
+}
+
+impl Repl {
+    pub fn new(mut vm : BFVM) -> Repl {
+        let last_output = Rc::new(Cell::new(b'\n'));
+        vm.set_output(Box::new(TrackedOutput { last : last_output.clone() }));
+
+        Repl {
+            vm,
+            editor : LineEditor::new(),
+            undo : vec![],
+            last_output
+        }
+    }
+
+    // Reads and runs lines until the end of input or :quit,
+    //  failing if a line can't be read.
+    pub fn run(&mut self) -> io::Result<VMResult> {
+        println!("BF REPL, type ':h' for help.");
+
+        let mut code = String::new();
+        loop {
+            // Keep the prompt off the end of any output
+            if self.last_output.get() != b'\n' {
+                println!();
+                self.last_output.set(b'\n');
+            }
+            print!("{}", if code.is_empty() { "bf> " } else { "... " });
+
+            let line = match self.editor.read_line()? {
+                Some(line) => String::from_utf8_lossy(&line).into_owned(),
+                // Ctrl-C at the prompt drops the line being typed
+                None if interrupt::interrupted() => {
+                    interrupt::clear();
+                    code.clear();
+                    continue;
+                },
+                None => {
+                    println!();
+                    return Ok(VMResult::Success);
+                }
+            };
+
+            // Commands still work part way through a loop, which
+            //  carries on at the next line of code
+            if line.trim_start().starts_with(':') {
+                let words : Vec<&str> = line.split_whitespace().collect();
+                if !self.command(&words) {
+                    return Ok(VMResult::Success);
+                }
+                continue;
+            }
+
+            code.push_str(&line);
+            match BFLexer::parse(code.clone()) {
+                LexResult::Success(tokens) => {
+                    self.save();
+                    self.execute(tokens, &Source::from_string(&code));
+                    code.clear();
+                },
+                // Wait for the rest of the loop
+                LexResult::Error { ref message, .. } if message == "Unmatched '['" => continue,
+                LexResult::Error { message, pos } => {
+                    println!("Parse error at character {}: {}", pos, message);
+                    code.clear();
+                }
+            }
+        }
+    }
+
+    // Runs a REPL command, returns false to quit.
+    fn command(&mut self, words : &[&str]) -> bool {
+        match (words[0], words.get(1)) {
+            (":t", radius) | (":tape", radius) => {
+                match radius.map(|radius| radius.parse::<usize>()) {
+                    Some(Err(_)) => println!("Expected a number, found '{}'", words[1]),
+                    Some(Ok(radius)) => println!("{}", format_tape_window(self.vm.memory(), self.vm.data_ptr(), radius)),
+                    None => println!("{}", format_tape_window(self.vm.memory(), self.vm.data_ptr(), 8))
+                }
+            },
+            (":r", _) | (":reset", _) => {
+                self.save();
+                self.vm.reset();
+            },
+            (":l", Some(file_name)) | (":load", Some(file_name)) => self.load(file_name),
+            (":l", None) | (":load", None) => println!("Expected a file name"),
+            (":u", _) | (":undo", _) => {
+                match self.undo.pop() {
+                    Some(snapshot) => {
+                        self.vm.memory_mut().copy_from_slice(&snapshot.mem);
+                        self.vm.set_data_ptr(snapshot.data_ptr);
+                    },
+                    None => println!("Nothing to undo")
+                }
+            },
+            (":q", _) | (":quit", _) => return false,
+            (":h", _) | (":help", _) => println!("{}", HELP),
+            (command, _) => println!("Unknown command '{}'. Type ':h' for help.", command)
+        }
+        true
+    }
+
+    fn load(&mut self, file_name : &str) {
+        let source = match Source::load(&[String::from(file_name)]) {
+            Ok(source) => source,
+            Err((_, error)) => {
+                println!("Unable to read '{}': {}", file_name, error);
+                return;
+            }
+        };
+
+        match BFLexer::parse(source.text.clone()) {
+            LexResult::Success(tokens) => {
+                self.save();
+                self.execute(tokens, &source);
+            },
+            LexResult::Error { message, pos } => println!("Parse error at character {}: {}", pos, message)
+        }
+    }
+
+    // Runs tokens against the tape, showing where they got to
+    //  if Ctrl-C stops them.
+    fn execute(&mut self, tokens : Vec<BFToken>, source : &Source) {
+        match self.vm.run(tokens.clone()) {
+            VMResult::Error { message, .. } => println!("Error: {}", message),
+            VMResult::Interrupted => {
+                interrupt::clear();
+                // Move past the ^C, and any output on the line
+                println!();
+                self.last_output.set(b'\n');
+                report::interrupted(&self.vm, &tokens, source, "interpreter");
+            },
+            VMResult::Success => ()
+        }
+    }
+
+    // Remembers the tape so the next change can be undone.
+    fn save(&mut self) {
+        if self.undo.len() == UNDO_LIMIT {
+            self.undo.remove(0);
+        }
+        self.undo.push(Snapshot {
+            mem : self.vm.memory().to_vec(),
+            data_ptr : self.vm.data_ptr()
+        });
+    }
+}
@@ -0,0 +1,43 @@
+// Runtime reports
+//
+// What's shown on stderr when a program fails or is stopped
+//  with Ctrl-C, by run and by the REPL and debugger, which
+//  carry on afterwards.
+
+use bf_lib::bf_lexer::BFToken;
+use bf_lib::bf_vm::BFVM;
+use bf_lib::bf_output::format_tape_window;
+
+use source::Source;
+
+// Shows a runtime error with the source of the token that
+//  failed, when it's known, and the tape around the data
+//  pointer.
+pub fn error(message : &str, inst_ptr : Option<usize>, bfvm : &BFVM, tokens : &[BFToken], source : &Source) {
+    eprintln!("Runtime error: {}", message);
+    if let Some(token) = inst_ptr.and_then(|inst_ptr| tokens.get(inst_ptr)) {
+        eprintln!("{}", source.snippet(token.source_pos, token.source_len));
+    }
+    tape(bfvm);
+}
+
+// Shows where the program was when Ctrl-C stopped it. Only
+//  the interpreter keeps track of which instruction it's on.
+pub fn interrupted(bfvm : &BFVM, tokens : &[BFToken], source : &Source, engine : &str) {
+    if engine == "interpreter" {
+        match tokens.get(bfvm.inst_ptr()) {
+            Some(token) => eprintln!("Interrupted after {} instructions at {}", bfvm.inst_count(), source.locate(token.source_pos)),
+            None => eprintln!("Interrupted after {} instructions at the end of the program", bfvm.inst_count())
+        }
+    } else {
+        eprintln!("Interrupted");
+    }
+    tape(bfvm);
+}
+
+// Shows the data pointer and the cells around it, after a
+//  program has failed or been stopped.
+fn tape(bfvm : &BFVM) {
+    eprintln!("data pointer: {}", bfvm.data_ptr());
+    eprintln!("{}", format_tape_window(bfvm.memory(), bfvm.data_ptr(), 4));
+}
@@ -0,0 +1,286 @@
+// Program source
+//
+// A program can be split over several files (or piped in
+//  on stdin), which are joined in order before lexing. The
+//  start of each file is remembered so positions in the
+//  joined text can be mapped back to the file they came from.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+// Name used for source read from stdin
+pub const STDIN : &str = "-";
+
+struct SourceFile {
+    name : String,
+
+    // Character offset of the file in the joined text
+    start : usize
+}
+
+pub struct Source {
+    pub text : String,
+    files : Vec<SourceFile>
+}
+
+// A position in one of the source files, lines and
+//  columns start at 1.
+pub struct Location<'a> {
+    pub file : &'a str,
+    pub line : usize,
+    pub column : usize
+}
+
+impl<'a> fmt::Display for Location<'a> {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+impl Source {
+    // Source given directly on the command line.
+    pub fn from_string(text : &str) -> Source {
+        Source {
+            text : String::from(text),
+            files : vec![SourceFile { name : String::from("<string>"), start : 0 }]
+        }
+    }
+
+    // Reads and joins the files in order, "-" reads stdin.
+    //  On failure returns the name of the file that couldn't
+    //  be read along with the reason.
+    pub fn load(names : &[String]) -> Result<Source, (String, io::Error)> {
+        let mut source = Source { text : String::new(), files : vec![] };
+
+        for name in names {
+            let mut text = String::new();
+            let result = if name == STDIN {
+                io::stdin().read_to_string(&mut text)
+            } else {
+                File::open(name).and_then(|mut file| file.read_to_string(&mut text))
+            };
+
+            if let Err(error) = result {
+                return Err((name.clone(), error));
+            }
+
+            source.files.push(SourceFile {
+                name : if name == STDIN { String::from("<stdin>") } else { name.clone() },
+                start : source.text.chars().count()
+            });
+            source.text.push_str(&text);
+        }
+
+        Ok(source)
+    }
+
+    // True if any of the source was read from stdin, in
+    //  which case stdin can't also be used for program input.
+    pub fn uses_stdin(&self) -> bool {
+        self.files.iter().any(|file| file.name == "<stdin>")
+    }
+
+    // Splits off anything after the first '!', which dbfi
+    //  style programs use to carry their own input.
+    pub fn split_input(&mut self) -> Option<String> {
+        let index = self.text.find('!')?;
+        let input = self.text[index + 1..].to_string();
+        self.text.truncate(index);
+        Some(input)
+    }
+
+    // Maps a character position in the joined text back to
+    //  the file, line and column it came from.
+    pub fn locate(&self, pos : usize) -> Location<'_> {
+        let index = self.files.iter().rposition(|file| file.start <= pos).unwrap_or(0);
+        let file = &self.files[index];
+
+        let mut location = Location { file : &file.name, line : 1, column : 1 };
+        for character in self.text.chars().skip(file.start).take(pos - file.start) {
+            if character == '\n' {
+                location.line += 1;
+                location.column = 1;
+            } else {
+                location.column += 1;
+            }
+        }
+
+        location
+    }
+
+    // Shows the line a run of characters starts on, with
+    //  them underlined in the same style as rustc:
+    //
+    //    --> prog.bf:3:3
+    //     |
+    //   3 | +[<]
+    //     |   ^
+    //
+    //  Long lines are cut down to the part around the column,
+    //  with "..." where text has been left out.
+    pub fn snippet(&self, pos : usize, len : usize) -> String {
+        let location = self.locate(pos);
+        let column = location.column - 1;
+
+        let line : String = self.text.chars().skip(pos - column).take_while(|c| *c != '\n').collect();
+        let line : Vec<char> = line.trim_end_matches('\r').chars().collect();
+
+        // Keep some of the line before the column, and fill the
+        //  rest of the window with what follows it.
+        let mut start = column.saturating_sub(SNIPPET_BEFORE);
+        let end = (start + SNIPPET_WIDTH).min(line.len());
+        if end - start < SNIPPET_WIDTH {
+            start = end.saturating_sub(SNIPPET_WIDTH).min(start);
+        }
+        let before = if start > 0 { "..." } else { "" };
+        let after = if end < line.len() { "..." } else { "" };
+        let shown : String = line[start..end].iter().collect();
+
+        // Tabs are kept so the carets line up however wide
+        //  they're shown.
+        let indent : String = before.chars().map(|_| ' ')
+            .chain(line[start..column.min(end)].iter().map(|&c| if c == '\t' { '\t' } else { ' ' }))
+            .collect();
+        let width = len.min(end.saturating_sub(column)).max(1);
+
+        let gutter = " ".repeat(location.line.to_string().len());
+        format!("{0}--> {1}\n{0} |\n{2} | {3}{4}{5}\n{0} | {6}{7}",
+            gutter, location, location.line, before, shown, after, indent, "^".repeat(width))
+    }
+}
+
+// Characters of a long line shown by Source::snippet, and
+//  how many of them come before the column.
+const SNIPPET_WIDTH : usize = 60;
+const SNIPPET_BEFORE : usize = 20;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Source joined from several files, as Source::load would
+    fn joined(files : &[(&str, &str)]) -> Source {
+        let mut source = Source { text : String::new(), files : vec![] };
+        for &(name, text) in files {
+            source.files.push(SourceFile { name : String::from(name), start : source.text.chars().count() });
+            source.text.push_str(text);
+        }
+        source
+    }
+
+    fn located(source : &Source, pos : usize) -> String {
+        source.locate(pos).to_string()
+    }
+
+    #[test]
+    fn locate_in_a_string() {
+        let source = Source::from_string("+\n+[\n<]");
+        assert_eq!(located(&source, 0), "<string>:1:1");
+        assert_eq!(located(&source, 3), "<string>:2:2");
+        assert_eq!(located(&source, 5), "<string>:3:1");
+    }
+
+    #[test]
+    fn locate_at_file_boundaries() {
+        let source = joined(&[("a.bf", "++\n"), ("b.bf", "[-]")]);
+        // The newline ending the first file is still in it
+        assert_eq!(located(&source, 2), "a.bf:1:3");
+        // The first character of the next file starts it again
+        assert_eq!(located(&source, 3), "b.bf:1:1");
+    }
+
+    #[test]
+    fn locate_in_the_second_file() {
+        let source = joined(&[("a.bf", "+\n+\n"), ("b.bf", ">\n>>[\n]")]);
+        assert_eq!(located(&source, 4), "b.bf:1:1");
+        assert_eq!(located(&source, 8), "b.bf:2:3");
+        assert_eq!(located(&source, 10), "b.bf:3:1");
+    }
+
+    #[test]
+    fn locate_in_loaded_files() {
+        let dir = ::std::env::temp_dir();
+        let first = dir.join(format!("bf-cli-locate-{}-a.bf", ::std::process::id()));
+        let second = dir.join(format!("bf-cli-locate-{}-b.bf", ::std::process::id()));
+        ::std::fs::write(&first, "++\n").unwrap();
+        ::std::fs::write(&second, "[-]").unwrap();
+
+        let names = vec![first.to_string_lossy().into_owned(), second.to_string_lossy().into_owned()];
+        let source = Source::load(&names);
+        let _ = ::std::fs::remove_file(&first);
+        let _ = ::std::fs::remove_file(&second);
+
+        let source = source.unwrap();
+        assert_eq!(source.text, "++\n[-]");
+        assert!(!source.uses_stdin());
+        assert_eq!(located(&source, 1), format!("{}:1:2", names[0]));
+        assert_eq!(located(&source, 3), format!("{}:1:1", names[1]));
+    }
+
+    #[test]
+    fn locate_in_stdin() {
+        let source = joined(&[("a.bf", "+"), ("<stdin>", "é[\n]")]);
+        assert!(source.uses_stdin());
+        // Positions count characters, not bytes
+        assert_eq!(located(&source, 2), "<stdin>:1:2");
+        assert_eq!(located(&source, 4), "<stdin>:2:1");
+    }
+
+    #[test]
+    fn split_input_at_the_first_bang() {
+        let mut source = Source::from_string(",[.,]!hello");
+        assert_eq!(source.split_input(), Some(String::from("hello")));
+        assert_eq!(source.text, ",[.,]");
+
+        // Only the first '!' ends the program, later ones are input
+        let mut source = Source::from_string(",.!a!b!");
+        assert_eq!(source.split_input(), Some(String::from("a!b!")));
+        assert_eq!(source.text, ",.");
+    }
+
+    #[test]
+    fn split_input_without_a_program_or_input() {
+        let mut source = Source::from_string("!input");
+        assert_eq!(source.split_input(), Some(String::from("input")));
+        assert_eq!(source.text, "");
+
+        let mut source = Source::from_string(",.!");
+        assert_eq!(source.split_input(), Some(String::new()));
+        assert_eq!(source.text, ",.");
+
+        let mut source = Source::from_string(",[.,]");
+        assert_eq!(source.split_input(), None);
+        assert_eq!(source.text, ",[.,]");
+    }
+
+    #[test]
+    fn snippet_underlines_short_lines() {
+        let source = Source::from_string("++\n+[<+]");
+        assert_eq!(source.snippet(5, 1), " --> <string>:2:3\n  |\n2 | +[<+]\n  |   ^");
+        assert_eq!(source.snippet(4, 10), " --> <string>:2:2\n  |\n2 | +[<+]\n  |  ^^^^");
+    }
+
+    #[test]
+    fn snippet_cuts_down_long_lines() {
+        let text = format!("{}[<+]{}", "+".repeat(100), "-".repeat(30000));
+        let source = Source::from_string(&text);
+        let snippet = source.snippet(101, 30000);
+        let lines : Vec<&str> = snippet.lines().collect();
+
+        assert_eq!(lines[2], format!("1 | ...{}[<+]{}...", "+".repeat(19), "-".repeat(37)));
+        assert_eq!(lines[3], format!("  | {}{}", " ".repeat(23), "^".repeat(40)));
+    }
+
+    #[test]
+    fn snippet_keeps_the_end_of_long_lines() {
+        let text = format!("{}[<+]", "+".repeat(100));
+        let source = Source::from_string(&text);
+        let snippet = source.snippet(101, 1);
+        let lines : Vec<&str> = snippet.lines().collect();
+
+        assert_eq!(lines[2], format!("1 | ...{}[<+]", "+".repeat(56)));
+        assert_eq!(lines[3], format!("  | {}^", " ".repeat(60)));
+    }
+}
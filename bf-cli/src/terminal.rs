@@ -0,0 +1,243 @@
+// Terminal handling
+//
+// Just enough termios to turn off line buffering and echo
+//  on stdin, so keys can be read as they're pressed. This
+//  is only implemented for Linux on the architectures that
+//  share the generic termios layout, elsewhere RawMode::enable
+//  fails and callers fall back to normal line input.
+
+use bf_lib::bf_throttle::Speed;
+
+use std::io;
+use std::io::prelude::*;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+// mips, powerpc, sparc and alpha lay out termios differently
+//  and use other flag values, so they get the fallback.
+#[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64", target_arch = "arm",
+    target_arch = "aarch64", target_arch = "riscv32", target_arch = "riscv64")))]
+mod raw {
+    use std::io;
+    use std::ptr;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    mod sys {
+        // struct termios from <termios.h>
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        pub struct Termios {
+            pub c_iflag : u32,
+            pub c_oflag : u32,
+            pub c_cflag : u32,
+            pub c_lflag : u32,
+            pub c_line : u8,
+            pub c_cc : [u8; 32],
+            pub c_ispeed : u32,
+            pub c_ospeed : u32
+        }
+
+        // c_lflag bits
+        pub const ISIG : u32 = 0o1;
+        pub const ICANON : u32 = 0o2;
+        pub const ECHO : u32 = 0o10;
+        pub const IEXTEN : u32 = 0o100000;
+
+        // c_cc indexes
+        pub const VTIME : usize = 5;
+        pub const VMIN : usize = 6;
+
+        pub const TCSANOW : i32 = 0;
+        pub const STDIN : i32 = 0;
+
+        extern "C" {
+            pub fn tcgetattr(fd : i32, termios : *mut Termios) -> i32;
+            pub fn tcsetattr(fd : i32, action : i32, termios : *const Termios) -> i32;
+        }
+    }
+
+    // Settings to put back if the program has to exit while
+    //  the terminal is in raw mode.
+    static ORIGINAL : Mutex<Option<sys::Termios>> = Mutex::new(None);
+
+    // The same settings for the SIGINT handler, which can't lock
+    //  a mutex. SAVED is only read while SAVED_SET is true.
+    static SAVED_SET : AtomicBool = AtomicBool::new(false);
+    static mut SAVED : sys::Termios = sys::Termios {
+        c_iflag : 0, c_oflag : 0, c_cflag : 0, c_lflag : 0,
+        c_line : 0, c_cc : [0; 32], c_ispeed : 0, c_ospeed : 0
+    };
+
+    // Puts the terminal into non-canonical mode with echo off,
+    //  restoring the original settings when dropped (including
+    //  when unwinding from a panic).
+    pub struct RawMode {
+        original : sys::Termios
+    }
+
+    impl RawMode {
+        // Reads wait until a key is pressed. Ctrl-C still raises
+        //  SIGINT so a program can always be stopped.
+        pub fn enable() -> io::Result<RawMode> {
+            RawMode::enable_with(1, true)
+        }
+
+        // Reads return straight away, reading nothing if no key
+        //  has been pressed.
+        pub fn enable_nonblocking() -> io::Result<RawMode> {
+            RawMode::enable_with(0, true)
+        }
+
+        // Ctrl-C is read as a key instead of raising SIGINT, for
+        //  readers that handle it themselves.
+        pub fn enable_without_signals() -> io::Result<RawMode> {
+            RawMode::enable_with(1, false)
+        }
+
+        fn enable_with(min_read : u8, signals : bool) -> io::Result<RawMode> {
+            use self::sys::*;
+
+            let mut termios = Termios {
+                c_iflag : 0, c_oflag : 0, c_cflag : 0, c_lflag : 0,
+                c_line : 0, c_cc : [0; 32], c_ispeed : 0, c_ospeed : 0
+            };
+            if unsafe { tcgetattr(STDIN, &mut termios) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let original = termios;
+            termios.c_lflag &= !(ICANON | ECHO | IEXTEN);
+            if !signals {
+                termios.c_lflag &= !ISIG;
+            }
+            termios.c_cc[VMIN] = min_read;
+            termios.c_cc[VTIME] = 0;
+
+            SAVED_SET.store(false, Ordering::SeqCst);
+            unsafe { ptr::addr_of_mut!(SAVED).write(original) };
+            SAVED_SET.store(true, Ordering::SeqCst);
+            set(&termios)?;
+
+            *ORIGINAL.lock().unwrap() = Some(original);
+            Ok(RawMode { original })
+        }
+    }
+
+    fn set(termios : &sys::Termios) -> io::Result<()> {
+        if unsafe { sys::tcsetattr(sys::STDIN, sys::TCSANOW, termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            let _ = set(&self.original);
+            SAVED_SET.store(false, Ordering::SeqCst);
+            *ORIGINAL.lock().unwrap() = None;
+        }
+    }
+
+    // Puts the terminal back from inside a signal handler, only
+    //  making async signal safe calls.
+    pub fn restore_from_signal() {
+        if SAVED_SET.load(Ordering::SeqCst) {
+            unsafe { sys::tcsetattr(sys::STDIN, sys::TCSANOW, ptr::addr_of!(SAVED)) };
+        }
+    }
+
+    // Puts the terminal back if it's in raw mode.
+    pub fn restore() {
+        if let Some(original) = ORIGINAL.lock().unwrap().take() {
+            let _ = set(&original);
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64", target_arch = "arm",
+    target_arch = "aarch64", target_arch = "riscv32", target_arch = "riscv64"))))]
+mod raw {
+    use std::io;
+
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> io::Result<RawMode> {
+            Err(io::Error::other("Raw terminal mode isn't supported on this platform"))
+        }
+
+        pub fn enable_nonblocking() -> io::Result<RawMode> {
+            RawMode::enable()
+        }
+
+        pub fn enable_without_signals() -> io::Result<RawMode> {
+            RawMode::enable()
+        }
+    }
+
+    // Never made, but dropped the same way as the real one
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+        }
+    }
+
+    pub fn restore() {
+    }
+
+    pub fn restore_from_signal() {
+    }
+}
+
+pub use self::raw::{RawMode, restore_from_signal};
+use self::raw::restore;
+
+// Puts the terminal back and exits, for when Ctrl-C can't
+//  wait for the program to stop.
+pub fn exit_interrupted() -> ! {
+    restore();
+    println!("^C");
+    process::exit(130);
+}
+
+// Program input read a key at a time while the terminal is
+//  in raw mode. If no_key is set reads don't wait, giving
+//  that value when no key has been pressed.
+pub struct RawInput {
+    pub no_key : Option<u8>
+}
+
+impl Read for RawInput {
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+        // Ctrl-C raises SIGINT, which makes this fail with
+        //  Interrupted for the VM to pick up.
+        let count = io::stdin().read(buf)?;
+
+        match (count, self.no_key) {
+            (0, Some(value)) if !buf.is_empty() => {
+                buf[0] = value;
+                Ok(1)
+            },
+            _ => Ok(count)
+        }
+    }
+}
+
+// Reads keys on another thread to change the speed of a
+//  running program: + and - double and halve it and space
+//  pauses. The terminal should already be in raw mode.
+pub fn control_speed(speed : Arc<Speed>) {
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut key = [0u8; 1];
+        while let Ok(1) = stdin.read(&mut key) {
+            match key[0] {
+                b'+' | b'=' => speed.set_rate(speed.rate().saturating_mul(2)),
+                b'-' | b'_' => speed.set_rate(speed.rate() / 2),
+                b' ' => speed.set_paused(!speed.is_paused()),
+                _ => ()
+            }
+        }
+    });
+}